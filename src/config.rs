@@ -1,19 +1,119 @@
-use config::{Config, File};
+use crate::isotp::IsoTpConfig;
+use crate::mqtt_handler::{PublishPolicies, PublishPolicy};
+use crate::obd::addressing::Addressing;
+use crate::obd::multi_pid::MAX_PIDS_PER_REQUEST;
+use crate::obd::schedule::{PollGroup, default_poll_groups};
+use crate::snapshot::OutputMode;
+use config::{Config, File, Value};
+use std::collections::HashMap;
 use std::path::Path;
+use std::time::Duration;
+
+/// Placeholder in `mqtt_base_topic` that is replaced by the vehicle's VIN.
+pub const VIN_PLACEHOLDER: &str = "{vin}";
 
 /// Struct to hold the application configuration.
 pub struct AppConfig {
     /// The name of the serial port.
     pub can_interface: String,
 
+    /// CAN identifier scheme used for requests, responses and flow control.
+    pub obd_addressing: Addressing,
+
     /// The MQTT broker host address.
     pub mqtt_host: String,
 
     /// The MQTT broker port number.
     pub mqtt_port: i64,
 
-    // The base topic of MQTT where data is pushed
+    /// Whether to connect with TLS (`mqtts://`).
+    pub mqtt_tls: bool,
+
+    /// CA certificate (PEM) used to verify the broker; the system CAs are used if unset.
+    pub mqtt_ca_file: Option<String>,
+
+    /// Client certificate (PEM) for mutual TLS.
+    pub mqtt_client_cert_file: Option<String>,
+
+    /// Private key (PEM) of the client certificate, if it is not part of the certificate file.
+    pub mqtt_client_key_file: Option<String>,
+
+    pub mqtt_username: Option<String>,
+
+    pub mqtt_password: Option<String>,
+
+    /// Client ID presented to the broker; empty lets the broker assign one.
+    pub mqtt_client_id: String,
+
+    // The base topic of MQTT where data is pushed, may contain a `{vin}` placeholder
     pub mqtt_base_topic: String,
+
+    /// Deadbands, minimum interval and heartbeat of signal topics, per signal.
+    pub publish_policies: PublishPolicies,
+
+    /// Whether values go to one topic per signal, a JSON snapshot topic or both.
+    pub mqtt_output: OutputMode,
+
+    /// Topic the JSON snapshot is published to.
+    pub snapshot_topic: String,
+
+    /// How often the JSON snapshot is published.
+    pub snapshot_interval: Duration,
+
+    /// Whether to publish Home Assistant MQTT discovery messages for every signal.
+    pub homeassistant_discovery: bool,
+
+    /// Topic prefix Home Assistant watches for discovery messages.
+    pub homeassistant_prefix: String,
+
+    /// Name of the Home Assistant device; the VIN is used if unset.
+    pub homeassistant_device_name: Option<String>,
+
+    /// Mode 01 PIDs to poll, grouped by request rate.
+    pub poll_groups: Vec<PollGroup>,
+
+    /// TOML or Torque CSV file with custom/manufacturer PID definitions.
+    pub custom_pids_file: Option<String>,
+
+    /// Poll rate of custom PIDs that don't set their own `rate_hz`.
+    pub custom_pid_rate_hz: f64,
+
+    /// Minimum age at which a decoded value counts as stale and is published as unavailable.
+    pub signal_stale_timeout: Duration,
+
+    /// How long a request may stay unanswered before it counts as timed out.
+    pub request_timeout: Duration,
+
+    /// Mode 01 PIDs sent in one request; 1 sends every PID on its own.
+    pub max_pids_per_request: usize,
+
+    /// How often request statistics are published to `<base>/diagnostics/requests`.
+    pub diagnostics_interval: Duration,
+
+    /// How often the stored, pending and permanent DTC lists are requested.
+    pub dtc_poll_interval: Duration,
+
+    /// Whether DTCs may be cleared (mode 04) from MQTT. Off by default since it mutates ECU state.
+    pub clear_dtc_enabled: bool,
+
+    /// Topic that triggers a mode 04 request; the outcome is published to `<topic>/result`.
+    pub clear_dtc_topic: String,
+
+    /// ISO-TP block size, STmin and timeouts used for multi-frame transfers.
+    pub isotp: IsoTpConfig,
+}
+
+impl AppConfig {
+    /// Replace the `{vin}` placeholder in every configured topic.
+    ///
+    /// # Arguments
+    ///
+    /// * `vin` - The VIN read from the vehicle, or a fallback if it could not be read.
+    pub fn substitute_vin(&mut self, vin: &str) {
+        self.mqtt_base_topic = self.mqtt_base_topic.replace(VIN_PLACEHOLDER, vin);
+        self.clear_dtc_topic = self.clear_dtc_topic.replace(VIN_PLACEHOLDER, vin);
+        self.snapshot_topic = self.snapshot_topic.replace(VIN_PLACEHOLDER, vin);
+    }
 }
 
 /// Load application configuration from a TOML file.
@@ -32,17 +132,293 @@ pub fn load_configuration(config_path: Option<&str>) -> Result<AppConfig, String
         load_default_paths()?
     };
 
+    let mqtt_base_topic = settings
+        .get_string("mqtt_base_topic")
+        .unwrap_or_else(|_| "default_topic".to_string());
+
+    let mqtt_tls = settings.get_bool("mqtt_tls").unwrap_or(false);
+    let mqtt_ca_file = settings.get_string("mqtt_ca_file").ok();
+    let mqtt_client_cert_file = settings.get_string("mqtt_client_cert_file").ok();
+    let mqtt_client_key_file = settings.get_string("mqtt_client_key_file").ok();
+    if !mqtt_tls
+        && (mqtt_ca_file.is_some()
+            || mqtt_client_cert_file.is_some()
+            || mqtt_client_key_file.is_some())
+    {
+        return Err("mqtt_ca_file and mqtt_client_*_file require mqtt_tls = true".to_string());
+    }
+    if mqtt_client_key_file.is_some() && mqtt_client_cert_file.is_none() {
+        return Err("mqtt_client_key_file requires mqtt_client_cert_file".to_string());
+    }
+
+    let obd_addressing = match settings.get_string("obd_addressing") {
+        Ok(value) => Addressing::parse(&value)
+            .ok_or_else(|| format!("Invalid obd_addressing \"{}\"", value))?,
+        Err(_) => Addressing::default(),
+    };
+
+    let max_pids_per_request = settings.get_int("max_pids_per_request").unwrap_or(6);
+    if !(1..=MAX_PIDS_PER_REQUEST as i64).contains(&max_pids_per_request) {
+        return Err(format!(
+            "max_pids_per_request must be between 1 and {}",
+            MAX_PIDS_PER_REQUEST
+        ));
+    }
+
+    let mqtt_output = match settings.get_string("mqtt_output") {
+        Ok(value) => {
+            OutputMode::parse(&value).ok_or_else(|| format!("Invalid mqtt_output \"{}\"", value))?
+        }
+        Err(_) => OutputMode::default(),
+    };
+
     Ok(AppConfig {
         can_interface: settings
             .get_string("can_interface")
             .unwrap_or_else(|_| "can0".to_string()),
+        obd_addressing,
         mqtt_host: settings
             .get_string("mqtt_host")
             .unwrap_or_else(|_| "default_host".to_string()),
-        mqtt_port: settings.get_int("mqtt_port").unwrap_or(1883),
-        mqtt_base_topic: settings
-            .get_string("mqtt_base_topic")
-            .unwrap_or_else(|_| "default_topic".to_string()),
+        mqtt_port: settings
+            .get_int("mqtt_port")
+            .unwrap_or(if mqtt_tls { 8883 } else { 1883 }),
+        mqtt_tls,
+        mqtt_ca_file,
+        mqtt_client_cert_file,
+        mqtt_client_key_file,
+        mqtt_username: settings.get_string("mqtt_username").ok(),
+        mqtt_password: settings.get_string("mqtt_password").ok(),
+        mqtt_client_id: settings.get_string("mqtt_client_id").unwrap_or_default(),
+        publish_policies: load_publish_policies(&settings)?,
+        mqtt_output,
+        snapshot_topic: settings
+            .get_string("snapshot_topic")
+            .unwrap_or_else(|_| format!("{}/snapshot", mqtt_base_topic)),
+        snapshot_interval: load_interval(
+            &settings,
+            "snapshot_interval_ms",
+            1000,
+            Duration::from_millis,
+        )?,
+        homeassistant_discovery: settings
+            .get_bool("homeassistant_discovery")
+            .unwrap_or(false),
+        homeassistant_prefix: settings
+            .get_string("homeassistant_prefix")
+            .unwrap_or_else(|_| "homeassistant".to_string()),
+        homeassistant_device_name: settings.get_string("homeassistant_device_name").ok(),
+        poll_groups: load_poll_groups(&settings)?,
+        custom_pids_file: settings.get_string("custom_pids_file").ok(),
+        custom_pid_rate_hz: settings.get_float("custom_pid_rate_hz").unwrap_or(1.0),
+        signal_stale_timeout: load_interval(
+            &settings,
+            "signal_stale_timeout_ms",
+            2000,
+            Duration::from_millis,
+        )?,
+        request_timeout: load_interval(
+            &settings,
+            "request_timeout_ms",
+            100,
+            Duration::from_millis,
+        )?,
+        max_pids_per_request: max_pids_per_request as usize,
+        diagnostics_interval: load_interval(
+            &settings,
+            "diagnostics_interval_secs",
+            10,
+            Duration::from_secs,
+        )?,
+        dtc_poll_interval: load_interval(
+            &settings,
+            "dtc_poll_interval_secs",
+            30,
+            Duration::from_secs,
+        )?,
+        clear_dtc_enabled: settings.get_bool("clear_dtc_enabled").unwrap_or(false),
+        clear_dtc_topic: settings
+            .get_string("clear_dtc_topic")
+            .unwrap_or_else(|_| format!("{}/cmd/clear_dtc", mqtt_base_topic)),
+        isotp: load_isotp_config(&settings)?,
+        mqtt_base_topic,
+    })
+}
+
+/// Reads an interval or timeout in the unit of `unit`, e.g. `Duration::from_millis`.
+///
+/// # Returns
+///
+/// Returns an error message if the value is zero or negative.
+fn load_interval(
+    settings: &Config,
+    key: &str,
+    default: u64,
+    unit: fn(u64) -> Duration,
+) -> Result<Duration, String> {
+    match settings.get_int(key).map(u64::try_from) {
+        Ok(Ok(value)) if value > 0 => Ok(unit(value)),
+        Ok(_) => Err(format!("{} must be greater than zero", key)),
+        Err(_) => Ok(unit(default)),
+    }
+}
+
+/// Reads the `[[poll_groups]]` tables, falling back to the built-in schedule if there are none.
+///
+/// # Returns
+///
+/// Returns an error message naming the group if a PID or rate is invalid.
+fn load_poll_groups(settings: &Config) -> Result<Vec<PollGroup>, String> {
+    let Ok(groups) = settings.get_array("poll_groups") else {
+        return Ok(default_poll_groups());
+    };
+
+    groups
+        .into_iter()
+        .enumerate()
+        .map(|(index, group)| parse_poll_group(index, group))
+        .collect()
+}
+
+/// Parses one `[[poll_groups]]` table, e.g. `{ name = "fast", pids = [0x0C, 0x11], rate_hz = 50 }`.
+fn parse_poll_group(index: usize, group: Value) -> Result<PollGroup, String> {
+    let mut table = group
+        .into_table()
+        .map_err(|err| format!("Invalid poll group {}: {}", index, err))?;
+
+    let name = match table.remove("name") {
+        Some(name) => name.into_string().map_err(|err| err.to_string())?,
+        None => format!("group{}", index),
+    };
+
+    let pids = table
+        .remove("pids")
+        .ok_or_else(|| format!("Poll group \"{}\" has no pids", name))?
+        .into_array()
+        .map_err(|err| format!("Invalid pids in poll group \"{}\": {}", name, err))?
+        .into_iter()
+        .map(|pid| {
+            pid.into_int()
+                .ok()
+                .and_then(|pid| u8::try_from(pid).ok())
+                .ok_or_else(|| format!("Invalid PID in poll group \"{}\"", name))
+        })
+        .collect::<Result<Vec<u8>, String>>()?;
+
+    let rate_hz = table
+        .remove("rate_hz")
+        .ok_or_else(|| format!("Poll group \"{}\" has no rate_hz", name))?
+        .into_float()
+        .map_err(|err| format!("Invalid rate_hz in poll group \"{}\": {}", name, err))?;
+    if !(rate_hz > 0.0 && rate_hz.is_finite()) {
+        return Err(format!(
+            "rate_hz of poll group \"{}\" must be greater than zero",
+            name
+        ));
+    }
+
+    Ok(PollGroup {
+        name,
+        pids,
+        rate_hz,
+    })
+}
+
+/// Reads the default publish policy from the `publish_*` keys and the per-signal overrides
+/// from the `[publish.<signal key>]` tables.
+///
+/// # Returns
+///
+/// Returns an error message naming the signal if an override is invalid.
+fn load_publish_policies(settings: &Config) -> Result<PublishPolicies, String> {
+    // A heartbeat of 0 never republishes; infinite and negative durations are rejected
+    let heartbeat = |secs: f64| {
+        Duration::try_from_secs_f64(secs)
+            .ok()
+            .map(|heartbeat| (!heartbeat.is_zero()).then_some(heartbeat))
+    };
+    let min_interval = |ms: f64| Duration::try_from_secs_f64(ms / 1000.0).ok();
+    let default = PublishPolicy {
+        deadband: settings.get_float("publish_deadband").unwrap_or(0.0),
+        deadband_percent: settings
+            .get_float("publish_deadband_percent")
+            .unwrap_or(0.0),
+        min_interval: match settings.get_int("publish_min_interval_ms") {
+            Ok(ms) => u64::try_from(ms)
+                .map(Duration::from_millis)
+                .map_err(|_| "publish_min_interval_ms must not be negative".to_string())?,
+            Err(_) => Duration::ZERO,
+        },
+        heartbeat: heartbeat(settings.get_float("publish_heartbeat_secs").unwrap_or(0.0))
+            .ok_or_else(|| {
+                "publish_heartbeat_secs must be a finite, non-negative number".to_string()
+            })?,
+    };
+
+    let mut signals = HashMap::new();
+    for (key, table) in settings.get_table("publish").unwrap_or_default() {
+        let mut table = table
+            .into_table()
+            .map_err(|err| format!("Invalid publish settings of \"{}\": {}", key, err))?;
+        let mut float = |field: &str| -> Result<Option<f64>, String> {
+            table
+                .remove(field)
+                .map(|value| value.into_float())
+                .transpose()
+                .map_err(|_| format!("Invalid {} in publish settings of \"{}\"", field, key))
+        };
+
+        let invalid = |field: &str| format!("Invalid {} in publish settings of \"{}\"", field, key);
+        let policy = PublishPolicy {
+            deadband: float("deadband")?.unwrap_or(default.deadband),
+            deadband_percent: float("deadband_percent")?.unwrap_or(default.deadband_percent),
+            min_interval: match float("min_interval_ms")? {
+                Some(ms) => min_interval(ms).ok_or_else(|| invalid("min_interval_ms"))?,
+                None => default.min_interval,
+            },
+            heartbeat: match float("heartbeat_secs")? {
+                Some(secs) => heartbeat(secs).ok_or_else(|| invalid("heartbeat_secs"))?,
+                None => default.heartbeat,
+            },
+        };
+        signals.insert(key, policy);
+    }
+
+    Ok(PublishPolicies { default, signals })
+}
+
+/// Reads the ISO-TP parameters, falling back to the defaults for any missing key.
+///
+/// # Returns
+///
+/// Returns an error message if a block size, STmin or timeout is out of range.
+fn load_isotp_config(settings: &Config) -> Result<IsoTpConfig, String> {
+    let defaults = IsoTpConfig::default();
+
+    let block_size = match settings.get_int("isotp_block_size") {
+        Ok(value) => u8::try_from(value)
+            .map_err(|_| "isotp_block_size must be between 0 and 255".to_string())?,
+        Err(_) => defaults.block_size,
+    };
+    let st_min = match settings.get_int("isotp_st_min") {
+        Ok(value @ (0x00..=0x7F | 0xF1..=0xF9)) => value as u8,
+        Ok(_) => {
+            return Err("isotp_st_min must be between 0x00 and 0x7F or 0xF1 and 0xF9".to_string());
+        }
+        Err(_) => defaults.st_min,
+    };
+    let timeout = |key: &str, default: Duration| match settings.get_int(key) {
+        Ok(ms) => u64::try_from(ms)
+            .map(Duration::from_millis)
+            .map_err(|_| format!("{} must not be negative", key)),
+        Err(_) => Ok(default),
+    };
+
+    Ok(IsoTpConfig {
+        block_size,
+        st_min,
+        n_bs_timeout: timeout("isotp_n_bs_timeout_ms", defaults.n_bs_timeout)?,
+        n_cr_timeout: timeout("isotp_n_cr_timeout_ms", defaults.n_cr_timeout)?,
     })
 }
 
@@ -1,2 +1,8 @@
 pub const OBD_REQUEST_ID: u16 = 0x7DF;
 pub const OBD_RESPONSE_ID: u16 = 0x7E8;
+pub const OBD_RESPONSE_ID_LAST: u16 = 0x7EF;
+pub const OBD_ENGINE_REQUEST_ID: u16 = 0x7E0;
+pub const OBD_FUNCTIONAL_ID_29BIT: u32 = 0x18DB33F1;
+pub const OBD_PHYSICAL_ID_29BIT: u32 = 0x18DA0000;
+pub const OBD_TESTER_ADDRESS: u32 = 0xF1;
+pub const OBD_ENGINE_ADDRESS: u32 = 0x10;
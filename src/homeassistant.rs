@@ -0,0 +1,337 @@
+// Home Assistant MQTT discovery: one retained sensor config per signal, grouped under a
+// device named after the vehicle.
+
+use crate::config::AppConfig;
+use crate::mqtt_handler::{STATUS_OFFLINE, STATUS_ONLINE};
+use crate::obd::info::VehicleInfo;
+use crate::vehicle::signals::{DisplayGroup, SignalDefinition, SignalRegistry};
+use serde_json::{Map, Value, json};
+
+/// Device name used when neither a name is configured nor the VIN could be read.
+const DEFAULT_DEVICE_NAME: &str = "Vehicle";
+
+/// Build the discovery config message of every signal.
+///
+/// The device is identified by the VIN, or by `homeassistant_device_name` if the VIN is
+/// unknown. Stale values (`unavailable`) become `unknown` in Home Assistant, and every
+/// sensor goes unavailable with `<base>/status`.
+///
+/// # Returns
+///
+/// Returns `(topic, payload)` pairs, e.g. `homeassistant/sensor/<vin>/engine_rpm/config`.
+pub fn discovery_messages(
+    registry: &SignalRegistry,
+    config: &AppConfig,
+    info: &VehicleInfo,
+) -> Vec<(String, String)> {
+    let device_name = config
+        .homeassistant_device_name
+        .as_deref()
+        .or(info.vin.as_deref())
+        .unwrap_or(DEFAULT_DEVICE_NAME);
+    let node_id = node_id(info.vin.as_deref().unwrap_or(device_name));
+
+    let mut device = Map::new();
+    device.insert("identifiers".to_string(), json!([node_id]));
+    device.insert("name".to_string(), json!(device_name));
+    if let Some(ecu_name) = &info.ecu_name {
+        device.insert("model".to_string(), json!(ecu_name));
+    }
+    if let Some(calibration_id) = info.calibration_ids.first() {
+        device.insert("sw_version".to_string(), json!(calibration_id));
+    }
+    let device = Value::Object(device);
+
+    registry
+        .iter()
+        .map(|signal| {
+            let topic = format!(
+                "{}/sensor/{}/{}/config",
+                config.homeassistant_prefix, node_id, signal.key
+            );
+            let payload = sensor_config(signal, config, &node_id, &device);
+            (topic, payload.to_string())
+        })
+        .collect()
+}
+
+fn sensor_config(
+    signal: &SignalDefinition,
+    config: &AppConfig,
+    node_id: &str,
+    device: &Value,
+) -> Value {
+    let base_topic = &config.mqtt_base_topic;
+    let mut sensor = json!({
+        "name": signal.name,
+        "unique_id": format!("{}_{}", node_id, signal.key),
+        "state_topic": format!("{}/{}", base_topic, signal.topic),
+        // Stale values are published as "unavailable"; "None" makes them unknown
+        "value_template": "{{ value | float(None) }}",
+        "suggested_display_precision": signal.precision,
+        "state_class": state_class(signal),
+        "availability_topic": format!("{}/status", base_topic),
+        "payload_available": STATUS_ONLINE,
+        "payload_not_available": STATUS_OFFLINE,
+        "device": device,
+    });
+
+    if !signal.unit.is_empty() {
+        sensor["unit_of_measurement"] = json!(signal.unit);
+    }
+    if let Some(device_class) = device_class(&signal.unit) {
+        sensor["device_class"] = json!(device_class);
+    }
+
+    sensor
+}
+
+/// The Home Assistant device class implied by a unit, if there is one.
+fn device_class(unit: &str) -> Option<&'static str> {
+    match unit {
+        "°C" => Some("temperature"),
+        "kPa" | "Pa" => Some("pressure"),
+        "V" => Some("voltage"),
+        "km/h" => Some("speed"),
+        "km" => Some("distance"),
+        "s" | "min" => Some("duration"),
+        _ => None,
+    }
+}
+
+/// Counters only grow until they are reset; everything else is a measurement.
+fn state_class(signal: &SignalDefinition) -> &'static str {
+    if signal.group == DisplayGroup::Counters {
+        "total_increasing"
+    } else {
+        "measurement"
+    }
+}
+
+/// A discovery node ID: only letters, digits, `_` and `-` are allowed.
+fn node_id(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_configuration;
+    use crate::obd::pids::builtin_signals;
+    use std::collections::BTreeSet;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const VIN: &str = "WVWZZZ1KZ6W000001";
+
+    fn config(settings: &str) -> AppConfig {
+        static FILES: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "homeassistant-test-{}-{}.toml",
+            std::process::id(),
+            FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&path, format!("mqtt_base_topic = \"car\"\n{}", settings)).unwrap();
+        let config = load_configuration(path.to_str());
+        std::fs::remove_file(&path).unwrap();
+        config.unwrap()
+    }
+
+    fn registry() -> SignalRegistry {
+        let mut signals = builtin_signals();
+        let mut custom = signals[0].clone();
+        custom.key = "boost".to_string();
+        custom.topic = "Boost".to_string();
+        custom.unit = "kPa".to_string();
+        custom.group = DisplayGroup::Custom;
+        signals.push(custom);
+        SignalRegistry::new(signals)
+    }
+
+    fn payloads(
+        registry: &SignalRegistry,
+        config: &AppConfig,
+        info: &VehicleInfo,
+    ) -> Vec<(String, Value)> {
+        discovery_messages(registry, config, info)
+            .into_iter()
+            .map(|(topic, payload)| (topic, serde_json::from_str(&payload).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn sensors_are_grouped_under_the_vin() {
+        let registry = registry();
+        let info = VehicleInfo {
+            vin: Some(VIN.to_string()),
+            calibration_ids: vec!["06A906032HN".to_string(), "SW 1037".to_string()],
+            ecu_name: Some("ECM-EngineControl".to_string()),
+            ..VehicleInfo::default()
+        };
+        let messages = payloads(&registry, &config(""), &info);
+        assert_eq!(messages.len(), registry.iter().count());
+
+        let (topic, payload) = messages
+            .iter()
+            .find(|(_, payload)| payload["name"] == "Engine RPM")
+            .unwrap();
+        assert_eq!(
+            topic,
+            &format!("homeassistant/sensor/{}/engine_rpm/config", VIN)
+        );
+        assert_eq!(payload["unique_id"], format!("{}_engine_rpm", VIN));
+        assert_eq!(payload["state_topic"], "car/RPM");
+        assert_eq!(payload["availability_topic"], "car/status");
+        assert_eq!(payload["payload_available"], STATUS_ONLINE);
+        assert_eq!(payload["payload_not_available"], STATUS_OFFLINE);
+        assert_eq!(payload["suggested_display_precision"], 2);
+        assert_eq!(
+            payload["device"],
+            json!({
+                "identifiers": [VIN],
+                "name": VIN,
+                "model": "ECM-EngineControl",
+                "sw_version": "06A906032HN",
+            })
+        );
+    }
+
+    #[test]
+    fn device_name_is_used_without_a_vin() {
+        let registry = registry();
+        let named =
+            config("homeassistant_prefix = \"ha\"\nhomeassistant_device_name = \"Track Car #2\"\n");
+
+        let messages = payloads(&registry, &named, &VehicleInfo::default());
+        let (topic, payload) = &messages[0];
+        assert_eq!(topic, "ha/sensor/Track_Car__2/engine_load/config");
+        assert_eq!(payload["unique_id"], "Track_Car__2_engine_load");
+        assert_eq!(
+            payload["device"],
+            json!({"identifiers": ["Track_Car__2"], "name": "Track Car #2"})
+        );
+
+        // The VIN still identifies the device when a name is configured
+        let info = VehicleInfo {
+            vin: Some(VIN.to_string()),
+            ..VehicleInfo::default()
+        };
+        let messages = payloads(&registry, &named, &info);
+        assert_eq!(messages[0].1["unique_id"], format!("{}_engine_load", VIN));
+        assert_eq!(messages[0].1["device"]["name"], "Track Car #2");
+
+        let messages = payloads(&registry, &config(""), &VehicleInfo::default());
+        assert_eq!(messages[0].1["device"]["name"], DEFAULT_DEVICE_NAME);
+    }
+
+    #[test]
+    fn classes_follow_unit_and_group() {
+        let registry = registry();
+        let info = VehicleInfo {
+            vin: Some(VIN.to_string()),
+            ..VehicleInfo::default()
+        };
+        let messages = payloads(&registry, &config(""), &info);
+        let sensor = |key: &str| {
+            let topic = format!("homeassistant/sensor/{}/{}/config", VIN, key);
+            &messages.iter().find(|(t, _)| *t == topic).unwrap().1
+        };
+
+        // (key, unit, device_class, state_class), at least one per display group
+        let expected = [
+            ("engine_rpm", json!("RPM"), Value::Null, "measurement"),
+            (
+                "vehicle_speed",
+                json!("km/h"),
+                json!("speed"),
+                "measurement",
+            ),
+            (
+                "control_module_voltage",
+                json!("V"),
+                json!("voltage"),
+                "measurement",
+            ),
+            ("actual_gear", Value::Null, Value::Null, "measurement"),
+            ("throttle_pos", json!("%"), Value::Null, "measurement"),
+            (
+                "coolant_temp",
+                json!("°C"),
+                json!("temperature"),
+                "measurement",
+            ),
+            (
+                "intake_pressure",
+                json!("kPa"),
+                json!("pressure"),
+                "measurement",
+            ),
+            ("maf_sensor", json!("g/s"), Value::Null, "measurement"),
+            (
+                "o2_sensor_voltage_b1s1",
+                json!("V"),
+                json!("voltage"),
+                "measurement",
+            ),
+            ("fuel_trim_short_b1", json!("%"), Value::Null, "measurement"),
+            (
+                "evap_system_vapor_pressure",
+                json!("Pa"),
+                json!("pressure"),
+                "measurement",
+            ),
+            (
+                "engine_run_time",
+                json!("s"),
+                json!("duration"),
+                "total_increasing",
+            ),
+            (
+                "time_with_mil",
+                json!("min"),
+                json!("duration"),
+                "total_increasing",
+            ),
+            (
+                "odometer",
+                json!("km"),
+                json!("distance"),
+                "total_increasing",
+            ),
+            (
+                "warmups_since_codes_cleared",
+                Value::Null,
+                Value::Null,
+                "total_increasing",
+            ),
+            ("egr_temp", json!("°C"), json!("temperature"), "measurement"),
+            ("boost", json!("kPa"), json!("pressure"), "measurement"),
+        ];
+        for (key, unit, device_class, state_class) in &expected {
+            let payload = sensor(key);
+            assert_eq!(&payload["unit_of_measurement"], unit, "{}", key);
+            assert_eq!(&payload["device_class"], device_class, "{}", key);
+            assert_eq!(payload["state_class"], *state_class, "{}", key);
+        }
+
+        let groups: BTreeSet<_> = expected
+            .iter()
+            .map(|(key, ..)| {
+                registry
+                    .iter()
+                    .find(|s| s.key == *key)
+                    .unwrap()
+                    .group
+                    .label()
+            })
+            .collect();
+        assert_eq!(groups.len(), DisplayGroup::ALL.len());
+    }
+}
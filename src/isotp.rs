@@ -0,0 +1,677 @@
+// ISO-TP (ISO 15765-2) transport layer.
+// Segments payloads longer than 7 bytes into First/Consecutive frames, reassembles them on
+// reception and drives the Flow Control handshake in both directions.
+
+use socketcan::{
+    CanFrame,
+    embedded_can::{Frame, Id},
+    tokio::CanSocket,
+};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Byte used to pad frames shorter than 8 bytes.
+const PADDING: u8 = 0x00;
+
+/// Largest payload that fits the 12-bit First Frame length field.
+pub const MAX_PAYLOAD_LEN: usize = 0xFFF;
+
+/// Timing and flow control parameters of the ISO-TP layer.
+#[derive(Debug, Clone)]
+pub struct IsoTpConfig {
+    /// Block size advertised in our Flow Control frames (0 = no further Flow Control).
+    pub block_size: u8,
+
+    /// Separation time advertised in our Flow Control frames, in raw STmin encoding.
+    pub st_min: u8,
+
+    /// N_Bs: how long to wait for a Flow Control frame from the receiver.
+    pub n_bs_timeout: Duration,
+
+    /// N_Cr: how long to wait for the next Consecutive Frame from the sender.
+    pub n_cr_timeout: Duration,
+}
+
+impl Default for IsoTpConfig {
+    fn default() -> Self {
+        IsoTpConfig {
+            block_size: 0,
+            st_min: 0,
+            n_bs_timeout: Duration::from_millis(1000),
+            n_cr_timeout: Duration::from_millis(1000),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum IsoTpError {
+    #[error("CAN error: {0}")]
+    Can(#[from] std::io::Error),
+    #[error("Malformed ISO-TP frame")]
+    MalformedFrame,
+    #[error("Consecutive frame out of sequence: expected {expected}, got {got}")]
+    SequenceMismatch { expected: u8, got: u8 },
+    #[error("Timed out waiting for flow control (N_Bs)")]
+    FlowControlTimeout,
+    #[error("Timed out waiting for consecutive frame (N_Cr)")]
+    ConsecutiveFrameTimeout,
+    #[error("Timed out waiting for a response")]
+    ResponseTimeout,
+    #[error("Receiver reported a buffer overflow")]
+    Overflow,
+    #[error("Payload of {0} bytes is too large for ISO-TP")]
+    PayloadTooLarge(usize),
+}
+
+/// Flow status carried by a Flow Control frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowStatus {
+    ContinueToSend,
+    Wait,
+    Overflow,
+}
+
+/// A single CAN data field interpreted according to its ISO-TP protocol control information.
+#[derive(Debug, PartialEq, Eq)]
+pub enum IsoTpFrame<'a> {
+    Single(&'a [u8]),
+    First {
+        length: usize,
+        data: &'a [u8],
+    },
+    Consecutive {
+        sequence: u8,
+        data: &'a [u8],
+    },
+    FlowControl {
+        status: FlowStatus,
+        block_size: u8,
+        st_min: u8,
+    },
+}
+
+impl<'a> IsoTpFrame<'a> {
+    /// Interpret the data field of a CAN frame.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The data field of the CAN frame.
+    ///
+    /// # Returns
+    ///
+    /// Returns the decoded frame, or `IsoTpError::MalformedFrame` if the PCI is invalid.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, IsoTpError> {
+        let pci = *bytes.first().ok_or(IsoTpError::MalformedFrame)?;
+
+        match pci >> 4 {
+            0x0 => {
+                let length = (pci & 0x0F) as usize;
+                if length == 0 || length >= bytes.len() {
+                    return Err(IsoTpError::MalformedFrame);
+                }
+                Ok(IsoTpFrame::Single(&bytes[1..=length]))
+            }
+            0x1 => {
+                if bytes.len() < 3 {
+                    return Err(IsoTpError::MalformedFrame);
+                }
+                let length = ((pci & 0x0F) as usize) << 8 | bytes[1] as usize;
+                // Anything up to 7 bytes must be sent as a Single Frame.
+                if length < 8 {
+                    return Err(IsoTpError::MalformedFrame);
+                }
+                Ok(IsoTpFrame::First {
+                    length,
+                    data: &bytes[2..],
+                })
+            }
+            0x2 => Ok(IsoTpFrame::Consecutive {
+                sequence: pci & 0x0F,
+                data: &bytes[1..],
+            }),
+            0x3 => {
+                if bytes.len() < 3 {
+                    return Err(IsoTpError::MalformedFrame);
+                }
+                let status = match pci & 0x0F {
+                    0x0 => FlowStatus::ContinueToSend,
+                    0x1 => FlowStatus::Wait,
+                    0x2 => FlowStatus::Overflow,
+                    _ => return Err(IsoTpError::MalformedFrame),
+                };
+                Ok(IsoTpFrame::FlowControl {
+                    status,
+                    block_size: bytes[1],
+                    st_min: bytes[2],
+                })
+            }
+            _ => Err(IsoTpError::MalformedFrame),
+        }
+    }
+}
+
+/// Convert a raw STmin byte into the separation time it encodes.
+pub fn st_min_duration(raw: u8) -> Duration {
+    match raw {
+        0x00..=0x7F => Duration::from_millis(raw as u64),
+        0xF1..=0xF9 => Duration::from_micros((raw - 0xF0) as u64 * 100),
+        // Reserved values must be treated as the longest separation time.
+        _ => Duration::from_millis(0x7F),
+    }
+}
+
+fn padded(bytes: &[u8]) -> [u8; 8] {
+    let mut data = [PADDING; 8];
+    data[..bytes.len()].copy_from_slice(bytes);
+    data
+}
+
+/// Build the data field of a Single Frame, or `None` if the payload needs segmentation.
+pub fn single_frame(payload: &[u8]) -> Option<[u8; 8]> {
+    if payload.is_empty() || payload.len() > 7 {
+        return None;
+    }
+    let mut data = [PADDING; 8];
+    data[0] = payload.len() as u8;
+    data[1..=payload.len()].copy_from_slice(payload);
+    Some(data)
+}
+
+/// Build the data field of a Flow Control (ContinueToSend) frame advertising `config`.
+pub fn flow_control_frame(config: &IsoTpConfig) -> [u8; 8] {
+    padded(&[0x30, config.block_size, config.st_min])
+}
+
+/// Split a payload into the CAN data fields that carry it.
+///
+/// Payloads of up to 7 bytes produce one Single Frame; longer payloads produce a First
+/// Frame followed by Consecutive Frames.
+///
+/// # Arguments
+///
+/// * `payload` - The complete service payload, starting with the service byte.
+///
+/// # Returns
+///
+/// Returns the frames in transmission order, or an error if the payload is empty or too large.
+pub fn segment(payload: &[u8]) -> Result<Vec<[u8; 8]>, IsoTpError> {
+    if payload.len() > MAX_PAYLOAD_LEN {
+        return Err(IsoTpError::PayloadTooLarge(payload.len()));
+    }
+    if let Some(frame) = single_frame(payload) {
+        return Ok(vec![frame]);
+    }
+    if payload.is_empty() {
+        return Err(IsoTpError::MalformedFrame);
+    }
+
+    let mut frames = Vec::with_capacity(payload.len().div_ceil(7));
+    let length = payload.len();
+    let mut first = [0x10 | (length >> 8) as u8, length as u8, 0, 0, 0, 0, 0, 0];
+    first[2..].copy_from_slice(&payload[..6]);
+    frames.push(first);
+
+    for (index, chunk) in payload[6..].chunks(7).enumerate() {
+        let mut data = [PADDING; 8];
+        data[0] = 0x20 | ((index + 1) & 0x0F) as u8;
+        data[1..=chunk.len()].copy_from_slice(chunk);
+        frames.push(data);
+    }
+
+    Ok(frames)
+}
+
+/// Outcome of feeding one frame into a `Reassembler`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Reassembly {
+    /// A complete payload has been received.
+    Complete(Vec<u8>),
+    /// The sender is waiting for a Flow Control frame before it continues.
+    FlowControlRequired,
+    /// A multi-frame payload is in progress.
+    InProgress,
+    /// The frame does not belong to a transfer we are receiving.
+    Ignored,
+}
+
+/// Incremental reassembly of ISO-TP payloads from one sender.
+///
+/// The reassembler does no I/O itself: it tells the caller when a Flow Control frame must be
+/// sent, so it can be driven from any receive loop.
+#[derive(Debug)]
+pub struct Reassembler {
+    block_size: u8,
+    n_cr_timeout: Duration,
+    buffer: Vec<u8>,
+    length: usize,
+    next_sequence: u8,
+    frames_in_block: u8,
+    last_frame: Option<Instant>,
+}
+
+impl Reassembler {
+    pub fn new(config: &IsoTpConfig) -> Self {
+        Reassembler {
+            block_size: config.block_size,
+            n_cr_timeout: config.n_cr_timeout,
+            buffer: Vec::new(),
+            length: 0,
+            next_sequence: 0,
+            frames_in_block: 0,
+            last_frame: None,
+        }
+    }
+
+    /// Returns `true` when no multi-frame transfer is in progress.
+    pub fn is_idle(&self) -> bool {
+        self.last_frame.is_none()
+    }
+
+    /// Feed the data field of a received frame.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The data field of the CAN frame.
+    /// * `now` - The reception time, used to enforce N_Cr.
+    ///
+    /// # Returns
+    ///
+    /// Returns what the caller has to do next, or an error if the transfer had to be aborted.
+    pub fn feed(&mut self, bytes: &[u8], now: Instant) -> Result<Reassembly, IsoTpError> {
+        match IsoTpFrame::parse(bytes)? {
+            IsoTpFrame::Single(payload) => {
+                self.reset();
+                Ok(Reassembly::Complete(payload.to_vec()))
+            }
+            IsoTpFrame::First { length, data } => {
+                self.buffer = data.to_vec();
+                self.length = length;
+                self.next_sequence = 1;
+                self.frames_in_block = 0;
+                self.last_frame = Some(now);
+                Ok(Reassembly::FlowControlRequired)
+            }
+            IsoTpFrame::Consecutive { sequence, data } => {
+                let Some(last_frame) = self.last_frame else {
+                    return Ok(Reassembly::Ignored);
+                };
+                if now.duration_since(last_frame) > self.n_cr_timeout {
+                    self.reset();
+                    return Err(IsoTpError::ConsecutiveFrameTimeout);
+                }
+                if sequence != self.next_sequence {
+                    let expected = self.next_sequence;
+                    self.reset();
+                    return Err(IsoTpError::SequenceMismatch {
+                        expected,
+                        got: sequence,
+                    });
+                }
+
+                let remaining = self.length - self.buffer.len();
+                self.buffer
+                    .extend_from_slice(&data[..data.len().min(remaining)]);
+                if self.buffer.len() >= self.length {
+                    let payload = std::mem::take(&mut self.buffer);
+                    self.reset();
+                    return Ok(Reassembly::Complete(payload));
+                }
+
+                self.next_sequence = (self.next_sequence + 1) & 0x0F;
+                self.last_frame = Some(now);
+                if self.block_size != 0 {
+                    self.frames_in_block += 1;
+                    if self.frames_in_block == self.block_size {
+                        self.frames_in_block = 0;
+                        return Ok(Reassembly::FlowControlRequired);
+                    }
+                }
+                Ok(Reassembly::InProgress)
+            }
+            IsoTpFrame::FlowControl { .. } => Ok(Reassembly::Ignored),
+        }
+    }
+
+    /// Abandon a partially received payload whose sender exceeded N_Cr.
+    ///
+    /// # Returns
+    ///
+    /// Returns `true` if a transfer was discarded.
+    pub fn expire(&mut self, now: Instant) -> bool {
+        match self.last_frame {
+            Some(last_frame) if now.duration_since(last_frame) > self.n_cr_timeout => {
+                self.reset();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.buffer.clear();
+        self.length = 0;
+        self.next_sequence = 0;
+        self.frames_in_block = 0;
+        self.last_frame = None;
+    }
+}
+
+/// Send a Flow Control (ContinueToSend) frame to `id`.
+pub async fn send_flow_control(
+    socket: &CanSocket,
+    id: Id,
+    config: &IsoTpConfig,
+) -> Result<(), IsoTpError> {
+    write_data(socket, id, &flow_control_frame(config)).await
+}
+
+async fn write_data(socket: &CanSocket, id: Id, data: &[u8; 8]) -> Result<(), IsoTpError> {
+    let frame = CanFrame::new(id, data).ok_or(IsoTpError::MalformedFrame)?;
+    socket.write_frame(frame).await?;
+    Ok(())
+}
+
+/// A point-to-point ISO-TP link between us and one ECU.
+///
+/// The channel reads directly from `rx`, so it is meant for request/response exchanges that
+/// run while nothing else consumes the socket (e.g. startup queries).
+pub struct IsoTpChannel<'a> {
+    tx: &'a CanSocket,
+    rx: &'a CanSocket,
+    tx_id: Id,
+    rx_id: Id,
+    config: &'a IsoTpConfig,
+}
+
+impl<'a> IsoTpChannel<'a> {
+    /// Create a channel that sends to `tx_id` and listens to `rx_id`.
+    pub fn new(
+        tx: &'a CanSocket,
+        rx: &'a CanSocket,
+        tx_id: Id,
+        rx_id: Id,
+        config: &'a IsoTpConfig,
+    ) -> Self {
+        IsoTpChannel {
+            tx,
+            rx,
+            tx_id,
+            rx_id,
+            config,
+        }
+    }
+
+    /// Send a payload, segmenting it and honouring the receiver's Flow Control when needed.
+    ///
+    /// # Arguments
+    ///
+    /// * `payload` - The complete service payload, starting with the service byte.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` once the last frame has been written.
+    pub async fn send(&self, payload: &[u8]) -> Result<(), IsoTpError> {
+        let mut frames = segment(payload)?.into_iter();
+
+        if let Some(first) = frames.next() {
+            write_data(self.tx, self.tx_id, &first).await?;
+        }
+
+        while frames.len() > 0 {
+            let (block_size, separation) = self.wait_flow_control().await?;
+            let mut sent = 0u8;
+            for frame in frames.by_ref() {
+                if sent > 0 && !separation.is_zero() {
+                    tokio::time::sleep(separation).await;
+                }
+                write_data(self.tx, self.tx_id, &frame).await?;
+                sent = sent.wrapping_add(1);
+                if block_size != 0 && sent == block_size {
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Receive one complete payload, sending Flow Control frames as required.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - How long to wait for the first frame of the response.
+    ///
+    /// # Returns
+    ///
+    /// Returns the reassembled payload, starting with the service byte.
+    pub async fn receive(&self, timeout: Duration) -> Result<Vec<u8>, IsoTpError> {
+        let mut reassembler = Reassembler::new(self.config);
+        let mut deadline = Instant::now() + timeout;
+
+        loop {
+            let Some(data) = self.next_frame(deadline).await? else {
+                return Err(if reassembler.is_idle() {
+                    IsoTpError::ResponseTimeout
+                } else {
+                    IsoTpError::ConsecutiveFrameTimeout
+                });
+            };
+
+            let now = Instant::now();
+            match reassembler.feed(&data, now)? {
+                Reassembly::Complete(payload) => return Ok(payload),
+                Reassembly::FlowControlRequired => {
+                    send_flow_control(self.tx, self.tx_id, self.config).await?;
+                    deadline = now + self.config.n_cr_timeout;
+                }
+                Reassembly::InProgress => deadline = now + self.config.n_cr_timeout,
+                Reassembly::Ignored => {}
+            }
+        }
+    }
+
+    /// Send a request and wait for the matching response.
+    pub async fn request(&self, payload: &[u8], timeout: Duration) -> Result<Vec<u8>, IsoTpError> {
+        self.send(payload).await?;
+        self.receive(timeout).await
+    }
+
+    async fn wait_flow_control(&self) -> Result<(u8, Duration), IsoTpError> {
+        let mut deadline = Instant::now() + self.config.n_bs_timeout;
+
+        loop {
+            let data = self
+                .next_frame(deadline)
+                .await?
+                .ok_or(IsoTpError::FlowControlTimeout)?;
+
+            if let Ok(IsoTpFrame::FlowControl {
+                status,
+                block_size,
+                st_min,
+            }) = IsoTpFrame::parse(&data)
+            {
+                match status {
+                    FlowStatus::ContinueToSend => {
+                        return Ok((block_size, st_min_duration(st_min)));
+                    }
+                    FlowStatus::Wait => deadline = Instant::now() + self.config.n_bs_timeout,
+                    FlowStatus::Overflow => return Err(IsoTpError::Overflow),
+                }
+            }
+        }
+    }
+
+    /// Wait for the next data frame from `rx_id`, returning `None` once `deadline` passes.
+    async fn next_frame(&self, deadline: Instant) -> Result<Option<Vec<u8>>, IsoTpError> {
+        let deadline = tokio::time::Instant::from_std(deadline);
+
+        loop {
+            match tokio::time::timeout_at(deadline, self.rx.read_frame()).await {
+                Err(_) => return Ok(None),
+                Ok(Err(e)) => return Err(e.into()),
+                Ok(Ok(CanFrame::Data(frame))) if frame.id() == self.rx_id => {
+                    return Ok(Some(frame.data().to_vec()));
+                }
+                Ok(Ok(_)) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reassemble(frames: &[[u8; 8]], config: &IsoTpConfig) -> Vec<Reassembly> {
+        let mut reassembler = Reassembler::new(config);
+        let now = Instant::now();
+        frames
+            .iter()
+            .map(|frame| reassembler.feed(frame, now).unwrap())
+            .collect()
+    }
+
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn single_frame_round_trip() {
+        let frames = segment(&[0x41, 0x0C, 0x1A, 0xF8]).unwrap();
+        assert_eq!(frames, vec![[0x04, 0x41, 0x0C, 0x1A, 0xF8, 0, 0, 0]]);
+        assert_eq!(
+            reassemble(&frames, &IsoTpConfig::default()),
+            vec![Reassembly::Complete(vec![0x41, 0x0C, 0x1A, 0xF8])]
+        );
+    }
+
+    #[test]
+    fn multi_frame_round_trip() {
+        let payload = payload(20);
+        let frames = segment(&payload).unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0][..2], [0x10, 20]);
+        assert_eq!(frames[1][0], 0x21);
+        assert_eq!(frames[2][0], 0x22);
+
+        assert_eq!(
+            reassemble(&frames, &IsoTpConfig::default()),
+            vec![
+                Reassembly::FlowControlRequired,
+                Reassembly::InProgress,
+                Reassembly::Complete(payload),
+            ]
+        );
+    }
+
+    #[test]
+    fn block_size_asks_for_flow_control_again() {
+        let config = IsoTpConfig {
+            block_size: 2,
+            ..IsoTpConfig::default()
+        };
+        let result = reassemble(&segment(&payload(40)).unwrap(), &config);
+        assert_eq!(result[2], Reassembly::FlowControlRequired);
+        assert_eq!(result[3], Reassembly::InProgress);
+        assert_eq!(result[4], Reassembly::FlowControlRequired);
+        assert!(matches!(result.last(), Some(Reassembly::Complete(_))));
+    }
+
+    #[test]
+    fn sequence_number_wraps_after_0xf() {
+        // 6 bytes in the First Frame and 7 per Consecutive Frame: 18 CFs run up to 0x2F, 0x20, 0x21, 0x22
+        let payload = payload(6 + 18 * 7);
+        let frames = segment(&payload).unwrap();
+        let sequences: Vec<u8> = frames[1..].iter().map(|frame| frame[0]).collect();
+        assert_eq!(sequences[14..], [0x2F, 0x20, 0x21, 0x22]);
+
+        assert_eq!(
+            reassemble(&frames, &IsoTpConfig::default()).last(),
+            Some(&Reassembly::Complete(payload))
+        );
+    }
+
+    #[test]
+    fn out_of_order_or_missing_consecutive_frame_aborts() {
+        let frames = segment(&payload(30)).unwrap();
+        let mut reassembler = Reassembler::new(&IsoTpConfig::default());
+        let now = Instant::now();
+
+        reassembler.feed(&frames[0], now).unwrap();
+        // The second Consecutive Frame arrives before the first one
+        assert!(matches!(
+            reassembler.feed(&frames[2], now),
+            Err(IsoTpError::SequenceMismatch {
+                expected: 1,
+                got: 2
+            })
+        ));
+        assert!(reassembler.is_idle());
+        assert_eq!(
+            reassembler.feed(&frames[1], now).unwrap(),
+            Reassembly::Ignored
+        );
+    }
+
+    #[test]
+    fn late_consecutive_frame_times_out() {
+        let config = IsoTpConfig::default();
+        let frames = segment(&payload(20)).unwrap();
+        let mut reassembler = Reassembler::new(&config);
+        let now = Instant::now();
+
+        reassembler.feed(&frames[0], now).unwrap();
+        let late = now + config.n_cr_timeout + Duration::from_millis(1);
+        assert!(matches!(
+            reassembler.feed(&frames[1], late),
+            Err(IsoTpError::ConsecutiveFrameTimeout)
+        ));
+        assert!(reassembler.is_idle());
+
+        reassembler.feed(&frames[0], now).unwrap();
+        assert!(reassembler.expire(late));
+    }
+
+    #[test]
+    fn first_frame_length_must_need_segmentation() {
+        assert!(matches!(
+            IsoTpFrame::parse(&[0x10, 0x07, 1, 2, 3, 4, 5, 6]),
+            Err(IsoTpError::MalformedFrame)
+        ));
+        assert_eq!(
+            IsoTpFrame::parse(&[0x10, 0x08, 1, 2, 3, 4, 5, 6]).unwrap(),
+            IsoTpFrame::First {
+                length: 8,
+                data: &[1, 2, 3, 4, 5, 6],
+            }
+        );
+        // The escape sequence for lengths above 4095 is not supported
+        assert!(matches!(
+            IsoTpFrame::parse(&[0x10, 0x00, 0x00, 0x00, 0x10, 0x00, 1, 2]),
+            Err(IsoTpError::MalformedFrame)
+        ));
+
+        assert_eq!(
+            segment(&payload(MAX_PAYLOAD_LEN)).unwrap()[0][..2],
+            [0x1F, 0xFF]
+        );
+        assert!(matches!(
+            segment(&payload(MAX_PAYLOAD_LEN + 1)),
+            Err(IsoTpError::PayloadTooLarge(4096))
+        ));
+    }
+
+    #[test]
+    fn st_min_encodings() {
+        assert_eq!(st_min_duration(0x00), Duration::ZERO);
+        assert_eq!(st_min_duration(0x7F), Duration::from_millis(127));
+        assert_eq!(st_min_duration(0xF1), Duration::from_micros(100));
+        assert_eq!(st_min_duration(0xF5), Duration::from_micros(500));
+        assert_eq!(st_min_duration(0xF9), Duration::from_micros(900));
+        // Reserved values
+        assert_eq!(st_min_duration(0x80), Duration::from_millis(127));
+        assert_eq!(st_min_duration(0xF0), Duration::from_millis(127));
+        assert_eq!(st_min_duration(0xFA), Duration::from_millis(127));
+    }
+}
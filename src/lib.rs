@@ -1,6 +1,9 @@
 pub mod config;
 pub mod constants;
 pub mod display;
+pub mod homeassistant;
+pub mod isotp;
 pub mod mqtt_handler;
 pub mod obd;
+pub mod snapshot;
 pub mod vehicle;
@@ -1,24 +1,60 @@
-use can_to_mqtt::vehicle::data::VehicleData;
+use can_to_mqtt::vehicle::data::{SignalState, VehicleData};
+use can_to_mqtt::vehicle::ecu::EcuState;
+use can_to_mqtt::vehicle::signals::{SignalDefinition, SignalRegistry};
 use futures_util::StreamExt;
 use socketcan::{
     CanFrame,
-    embedded_can::{Frame, Id, StandardId},
+    embedded_can::{Frame, Id},
     tokio::CanSocket,
 };
+use std::collections::{BTreeMap, BTreeSet};
 use std::error::Error;
+use std::time::{Duration, Instant, SystemTime};
 
-use can_to_mqtt::constants::OBD_RESPONSE_ID;
 use can_to_mqtt::display::display_vehicle_data;
-use can_to_mqtt::obd::request::send_obd_request;
+use can_to_mqtt::homeassistant::discovery_messages;
+use can_to_mqtt::isotp::{IsoTpChannel, Reassembly, send_flow_control};
+use can_to_mqtt::obd::addressing::id_to_hex;
+use can_to_mqtt::obd::classify::{NegativeResponseLog, ResponseKind, classify};
+use can_to_mqtt::obd::custom::{CustomSignal, load_custom_signals, poll_rates, response_headers};
+use can_to_mqtt::obd::discovery::discover_supported_pids;
+use can_to_mqtt::obd::dtc::{
+    ClearResult, DtcKind, TroubleCodes, parse_clear_response, parse_dtc_response,
+};
+use can_to_mqtt::obd::freeze_frame::{FreezeFrame, freeze_frame_pids, parse_freeze_frame_response};
+use can_to_mqtt::obd::info::{VehicleInfo, read_vehicle_info};
+use can_to_mqtt::obd::multi_pid::{MultiPidClient, split_response};
+use can_to_mqtt::obd::pids::builtin_signals;
+use can_to_mqtt::obd::readiness::{
+    PID_DRIVE_CYCLE_STATUS, PID_MONITOR_STATUS, Readiness, parse_readiness_response,
+};
+use can_to_mqtt::obd::request::{send_service_request, send_signal_request};
 use can_to_mqtt::obd::response::parse_obd_response;
+use can_to_mqtt::obd::schedule::PollScheduler;
+use can_to_mqtt::obd::tracker::{RequestKey, RequestTracker};
+use can_to_mqtt::obd::uds::{
+    READ_DATA_BY_IDENTIFIER, RESPONSE_PENDING, RESPONSE_PENDING_TIMEOUT, UdsClient, batch_reads,
+};
+use can_to_mqtt::snapshot::{signals_json, snapshot_json};
 
 use can_to_mqtt::config::AppConfig;
 use can_to_mqtt::config::load_configuration;
-use can_to_mqtt::mqtt_handler::{publish_if_changed, setup_mqtt};
+use can_to_mqtt::mqtt_handler::{
+    MqttSession, PublishPolicies, has_changed, publish_event, publish_if_changed, publish_signal,
+};
 use gumdrop::Options;
 use paho_mqtt as mqtt;
 use tokio;
 
+/// Longest pass of the polling loop, so MQTT commands and DTC polls are handled even with slow groups.
+const MAX_CYCLE_TIME: Duration = Duration::from_millis(100);
+
+/// Payload published in place of a value that has gone stale.
+const UNAVAILABLE: &str = "unavailable";
+
+/// How long to wait for the ECU to answer a mode 04 request.
+const CLEAR_DTC_TIMEOUT: Duration = Duration::from_secs(2);
+
 /// Define options for the program.
 #[derive(Debug, Options)]
 struct MyOptions {
@@ -32,107 +68,283 @@ struct MyOptions {
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
     let opts = MyOptions::parse_args_default_or_exit();
-    let config = load_config_or_exit(opts.config.as_deref());
-
-    let mut socket_rx = CanSocket::open(&config.can_interface)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-    let socket_tx = CanSocket::open(&config.can_interface)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-
-    let mut vehicle_data = VehicleData::default();
-
-    // Separate high-frequency PIDs
-    let high_freq_pids = [
-        (0x0C, "Engine RPM"),
-        (0x0D, "Vehicle speed"),
-        (0x04, "Engine load"),
-        (0x0E, "Timing advance"),
-        (0x11, "Throttle position"),
-    ];
-
-    // Regular frequency PIDs
-    let regular_pids = [
-        // Mode 01 PIDs
-        (0x05, "Coolant temperature"),
-        (0x06, "Short term fuel trim Bank 1"),
-        (0x07, "Long term fuel trim Bank 1"),
-        (0x08, "Short term fuel trim Bank 2"),
-        (0x09, "Long term fuel trim Bank 2"),
-        (0x0A, "Fuel pressure"),
-        (0x0B, "Intake manifold pressure"),
-        (0x0F, "Intake air temperature"),
-        (0x10, "MAF sensor"),
-        (0x14, "O2 Sensor Voltage B1S1"),
-        (0x15, "O2 Sensor Voltage B1S2"),
-        (0x16, "O2 Sensor Voltage B1S3"),
-        (0x17, "O2 Sensor Voltage B1S4"),
-        (0x1F, "Run time since engine start"),
-        (0x21, "Distance traveled with MIL on"),
-        (0x22, "Fuel rail pressure relative to manifold"),
-        (0x23, "Fuel rail pressure"),
-        (0x2C, "Commanded EGR"),
-        (0x2D, "EGR Error"),
-        (0x2E, "Commanded evaporative purge"),
-        (0x2F, "Fuel Level"),
-        (0x30, "Warm-ups since codes cleared"),
-        (0x31, "Distance traveled since codes cleared"),
-        (0x33, "Barometric pressure"),
-        (0x42, "Control module voltage"),
-        (0x43, "Absolute load value"),
-        (0x44, "Commanded equivalence ratio"),
-        (0x45, "Relative throttle position"),
-        (0x46, "Ambient temperature"),
-        (0x47, "Absolute throttle position B"),
-        (0x48, "Absolute throttle position C"),
-        (0x49, "Accelerator pedal position D"),
-        (0x4A, "Accelerator pedal position E"),
-        (0x4B, "Accelerator pedal position F"),
-        (0x4C, "Commanded throttle actuator"),
-        (0x4D, "Time run with MIL on"),
-        (0x4E, "Time since trouble codes cleared"),
-        (0x52, "Ethanol fuel %"),
-        (0x5C, "Engine oil temperature"),
-        (0x5E, "Engine fuel rate"),
-    ];
-
-    let mut regular_cycle_counter = 0;
+    let mut config = load_config_or_exit(opts.config.as_deref());
+
+    let mut socket_rx = CanSocket::open(&config.can_interface).map_err(std::io::Error::other)?;
+    let socket_tx = CanSocket::open(&config.can_interface).map_err(std::io::Error::other)?;
+
+    // Every signal that can be decoded, displayed and published
+    let mut registry = SignalRegistry::new(builtin_signals());
+    let custom_signals = load_custom_signals_or_exit(&config);
+    registry.extend(custom_signals.iter().map(|custom| custom.signal.clone()));
+    // ECUs outside the OBD response IDs that custom PIDs are requested from
+    let custom_responses = response_headers(&custom_signals);
+    for key in config.publish_policies.signals.keys() {
+        if !registry.iter().any(|signal| &signal.key == key) {
+            eprintln!("Ignoring publish settings of unknown signal \"{}\"", key);
+        }
+    }
+
+    let addressing = config.obd_addressing;
+    let engine_response_id = addressing.engine_response_id();
+    // The request ID of the ECU answering on a frame's ID, or `None` for other traffic
+    let ecu_request_id = |response_id: Id| {
+        custom_responses.get(&response_id).copied().or_else(|| {
+            addressing
+                .is_response_id(response_id)
+                .then(|| addressing.physical_request_id(response_id))
+                .flatten()
+        })
+    };
+    // Where the answers to requests sent to a custom header, or without one, arrive
+    let response_id_of = |header: Option<Id>| {
+        header.map_or(engine_response_id, |header| {
+            custom_responses
+                .iter()
+                .find(|(_, request_id)| **request_id == header)
+                .map_or(header, |(response_id, _)| *response_id)
+        })
+    };
+
+    // Ask the engine ECU which PIDs it supports before building the polling schedule
+    let engine_channel = IsoTpChannel::new(
+        &socket_tx,
+        &socket_rx,
+        addressing.engine_request_id(),
+        engine_response_id,
+        &config.isotp,
+    );
+    let supported_pids = match discover_supported_pids(&engine_channel).await {
+        Ok(supported) => Some(supported),
+        Err(e) => {
+            eprintln!("Supported PID discovery failed, polling all PIDs: {}", e);
+            None
+        }
+    };
+
+    // Identify the vehicle so its data can be told apart from other cars
+    let vehicle_info = read_vehicle_info(&engine_channel).await;
+    config.substitute_vin(vehicle_info.vin.as_deref().unwrap_or("unknown"));
+
+    let mut mqtt_session = MqttSession::new(&config).map_err(std::io::Error::other)?;
+    // Retained Home Assistant sensor configs, republished after every reconnect
+    let discovery = if config.homeassistant_discovery {
+        discovery_messages(&registry, &config, &vehicle_info)
+    } else {
+        Vec::new()
+    };
+    if config.clear_dtc_enabled {
+        mqtt_session.subscribe(&config.clear_dtc_topic);
+    }
+
+    // State per responding ECU, keyed by response ID; the engine ECU is always present
+    let mut ecus = BTreeMap::new();
+    ecus.insert(
+        engine_response_id,
+        EcuState::new(
+            engine_response_id,
+            addressing.engine_request_id(),
+            addressing,
+            &config.isotp,
+        ),
+    );
+    let mut last_dtc_poll: Option<Instant> = None;
+    // When an unanswered mode 04 request counts as timed out
+    let mut clear_dtc_deadline: Option<Instant> = None;
+    let mut clear_dtc_result: Option<ClearResult> = None;
+    // Correlates the engine ECU's answers with the requests sent to it
+    let mut tracker = RequestTracker::new(config.request_timeout);
+    // Reads UDS DIDs for custom PIDs of service 0x22
+    let mut uds = UdsClient::new(config.request_timeout);
+    // Packs several mode 01 PIDs into one request while the engine ECU answers them
+    let mut multi_pid = MultiPidClient::new(
+        config.max_pids_per_request,
+        config.request_timeout,
+        supported_pids.clone(),
+    );
+    let mut last_diagnostics_publish: Option<Instant> = None;
+    let mut last_snapshot_publish: Option<Instant> = None;
+
+    // Poll the configured groups, skipping PIDs the ECU does not answer
+    let mut scheduler = PollScheduler::new(&config.poll_groups, supported_pids.as_ref());
+    for (request, rate_hz) in poll_rates(&custom_signals) {
+        scheduler.add(request, rate_hz);
+    }
 
     loop {
-        // Request high-frequency PIDs every cycle
-        for (pid, desc) in high_freq_pids.iter() {
-            if let Err(e) = send_request(&socket_tx, *pid).await {
-                eprintln!("Error sending request for {}: {}", desc, e);
+        // Handle commands received over MQTT
+        while let Some(msg) = mqtt_session.poll_message() {
+            // Ignore retained commands so a stale message can't clear codes on every reconnect.
+            if config.clear_dtc_enabled && msg.topic() == config.clear_dtc_topic && !msg.retained()
+            {
+                match send_service_request(&socket_tx, addressing.request_id(), &[0x04]).await {
+                    Ok(()) => {
+                        clear_dtc_deadline = Some(Instant::now() + CLEAR_DTC_TIMEOUT);
+                        tracker.sent(RequestKey::new(0x04, None), Instant::now());
+                    }
+                    Err(e) => eprintln!("Error requesting DTC clear: {}", e),
+                }
             }
         }
 
-        // Request regular PIDs every 10 cycles (200ms / 20ms = 10)
-        if regular_cycle_counter == 0 {
-            for (pid, desc) in regular_pids.iter() {
-                if let Err(e) = send_request(&socket_tx, *pid).await {
-                    eprintln!("Error sending request for {}: {}", desc, e);
+        // Request the PIDs of every group that is due
+        let (uds_requests, requests): (Vec<_>, Vec<_>) = scheduler
+            .due_requests(Instant::now())
+            .into_iter()
+            .partition(|request| request.service == READ_DATA_BY_IDENTIFIER);
+        let (pid_batches, requests) = multi_pid.batch(requests);
+        for batch in pid_batches {
+            match multi_pid.send(&socket_tx, addressing, &batch).await {
+                Ok(()) => {
+                    let now = Instant::now();
+                    for pid in &batch.pids {
+                        tracker.sent(RequestKey::new(0x01, Some(*pid as u16)), now);
+                    }
+                }
+                Err(e) => eprintln!("Error requesting PIDs {:02X?}: {}", batch.pids, e),
+            }
+        }
+        for request in requests {
+            match send_signal_request(&socket_tx, addressing, &request).await {
+                // Only the engine ECU's answers reach the tracker, so only its requests are tracked
+                Ok(()) if response_id_of(request.header) == engine_response_id => {
+                    tracker.sent(RequestKey::from(&request), Instant::now())
+                }
+                Ok(()) => {}
+                Err(e) => eprintln!(
+                    "Error sending request {}: {}",
+                    RequestKey::from(&request),
+                    e
+                ),
+            }
+        }
+        for batch in batch_reads(&uds_requests, &registry) {
+            match uds
+                .read_data_by_identifier(
+                    &socket_tx,
+                    addressing,
+                    &batch,
+                    response_id_of(batch.header),
+                )
+                .await
+            {
+                Ok(()) if response_id_of(batch.header) == engine_response_id => {
+                    let now = Instant::now();
+                    for did in &batch.dids {
+                        tracker.sent(RequestKey::new(READ_DATA_BY_IDENTIFIER, Some(*did)), now);
+                    }
                 }
+                Ok(()) => {}
+                Err(e) => eprintln!("Error reading DIDs {:04X?}: {}", batch.dids, e),
             }
         }
 
-        let timeout = tokio::time::sleep(tokio::time::Duration::from_millis(20));
-        tokio::pin!(timeout);
+        // Request the DTC lists every `dtc_poll_interval`
+        if last_dtc_poll.is_none_or(|last| last.elapsed() >= config.dtc_poll_interval) {
+            for kind in DtcKind::ALL {
+                match send_service_request(&socket_tx, addressing.request_id(), &[kind.service()])
+                    .await
+                {
+                    Ok(()) => tracker.sent(RequestKey::new(kind.service(), None), Instant::now()),
+                    Err(e) => eprintln!("Error requesting {:?} DTCs: {}", kind, e),
+                }
+            }
+            // Freeze frame 0 is the snapshot taken when the stored DTC set
+            for pid in freeze_frame_pids().filter(|pid| {
+                *pid == 0x02 || supported_pids.as_ref().is_none_or(|s| s.contains(pid))
+            }) {
+                match send_service_request(&socket_tx, addressing.request_id(), &[0x02, pid, 0x00])
+                    .await
+                {
+                    Ok(()) => tracker.sent(RequestKey::new(0x02, Some(pid as u16)), Instant::now()),
+                    Err(e) => eprintln!("Error requesting freeze frame PID {:02X}: {}", pid, e),
+                }
+            }
+            last_dtc_poll = Some(Instant::now());
+        }
 
-        let expected_responses = if regular_cycle_counter == 0 {
-            high_freq_pids.len() + regular_pids.len()
-        } else {
-            high_freq_pids.len()
-        };
+        // Handle responses until the next group is due
+        let now = Instant::now();
+        let deadline = scheduler
+            .next_due()
+            .map_or(now + MAX_CYCLE_TIME, |due| due.min(now + MAX_CYCLE_TIME));
+        let timeout = tokio::time::sleep_until(deadline.into());
+        tokio::pin!(timeout);
 
-        let mut responses_received = 0;
-        while responses_received < expected_responses {
+        loop {
             tokio::select! {
                 frame = socket_rx.next() => {
                     match frame {
                         Some(Ok(frame)) => {
-                            if let CanFrame::Data(frame) = frame {
-                                if frame.id() == Id::Standard(StandardId::new(OBD_RESPONSE_ID).unwrap()) {
-                                    parse_obd_response(&frame, &mut vehicle_data);
-                                    responses_received += 1;
+                            if let CanFrame::Data(frame) = frame
+                                && let Some(request_id) = ecu_request_id(frame.id())
+                            {
+                                let response_id = frame.id();
+                                let ecu = ecus.entry(response_id).or_insert_with(|| {
+                                    EcuState::new(response_id, request_id, addressing, &config.isotp)
+                                });
+                                match ecu.reassembler.feed(frame.data(), Instant::now()) {
+                                    Ok(Reassembly::Complete(payload)) => {
+                                        if ecu.is_engine {
+                                            multi_pid.received(&payload);
+                                        }
+                                        // Multi-PID and multi-DID answers are decoded one PID or DID at a time
+                                        let payloads = match split_response(&payload) {
+                                            Ok(payloads) => payloads,
+                                            Err(e) => {
+                                                eprintln!("Error splitting multi-PID response: {}", e);
+                                                vec![payload]
+                                            }
+                                        };
+                                        let payloads: Vec<Vec<u8>> = payloads
+                                            .into_iter()
+                                            .flat_map(|payload| {
+                                                match uds.handle_response(&payload, response_id, Instant::now()) {
+                                                    Ok(response) => {
+                                                        if !response.unanswered.is_empty() {
+                                                            eprintln!(
+                                                                "ECU {} left out DIDs {:04X?}",
+                                                                id_to_hex(response_id),
+                                                                response.unanswered
+                                                            );
+                                                        }
+                                                        response.payloads
+                                                    }
+                                                    Err(e) => {
+                                                        eprintln!("UDS error: {}", e);
+                                                        vec![payload]
+                                                    }
+                                                }
+                                            })
+                                            .collect();
+                                        for payload in payloads {
+                                            // Other ECUs may answer the same request; track the engine ECU only
+                                            if ecu.is_engine {
+                                                tracker.received(&payload, Instant::now());
+                                            }
+                                            // The ECU needs more time to clear its codes
+                                            if let [0x7F, 0x04, RESPONSE_PENDING, ..] = payload[..]
+                                                && clear_dtc_deadline.is_some()
+                                            {
+                                                clear_dtc_deadline = Some(Instant::now() + RESPONSE_PENDING_TIMEOUT);
+                                            }
+                                            let clear_result = handle_response(&payload, &registry, ecu);
+                                            if clear_result.is_some() && clear_dtc_deadline.take().is_some() {
+                                                clear_dtc_result = clear_result;
+                                                // Refresh the DTC lists right away
+                                                last_dtc_poll = None;
+                                            }
+                                        }
+                                    }
+                                    Ok(Reassembly::FlowControlRequired) => {
+                                        if let Err(e) =
+                                            send_flow_control(&socket_tx, ecu.request_id, &config.isotp).await
+                                        {
+                                            eprintln!("Error sending flow control: {}", e);
+                                        }
+                                    }
+                                    Ok(_) => {}
+                                    Err(e) => eprintln!("Error reassembling response: {}", e),
                                 }
                             }
                         }
@@ -146,15 +358,134 @@ async fn main() -> std::io::Result<()> {
             }
         }
 
-        display_vehicle_data(&vehicle_data);
+        tracker.expire(Instant::now());
+        multi_pid.expire(Instant::now());
+        // Drop transfers whose next consecutive frame did not arrive within N_Cr
+        for ecu in ecus.values_mut() {
+            if ecu.reassembler.expire(Instant::now()) {
+                eprintln!(
+                    "Discarded incomplete response from ECU {}",
+                    id_to_hex(ecu.response_id)
+                );
+            }
+        }
 
-        let mqtt_client = setup_mqtt(&config);
-        if let Err(e) = publish_vehicle_data(&mqtt_client, &vehicle_data, &config) {
-            eprintln!("Error publishing to MQTT: {}", e);
+        if clear_dtc_deadline.is_some_and(|deadline| Instant::now() > deadline) {
+            clear_dtc_deadline = None;
+            clear_dtc_result = Some(ClearResult::NoResponse);
         }
 
-        // Update cycle counter
-        regular_cycle_counter = (regular_cycle_counter + 1) % 10;
+        // Values count as stale once their PID missed a few polls
+        let now = Instant::now();
+        let stale_after = |signal: &SignalDefinition| {
+            scheduler.stale_after(&signal.request, config.signal_stale_timeout)
+        };
+
+        let engine_data = &ecus[&engine_response_id].vehicle_data;
+        display_vehicle_data(&registry, engine_data, &|signal| {
+            engine_data.state(&signal.key, now, stale_after(signal))
+        });
+
+        if mqtt_session.ensure_connected() {
+            let changed_discovery: Vec<(String, String)> = discovery
+                .iter()
+                .filter(|(topic, payload)| has_changed(topic, payload).unwrap_or(true))
+                .cloned()
+                .collect();
+            if !changed_discovery.is_empty() {
+                mqtt_session.publish_in_background(move |client| {
+                    if let Err(e) = publish_discovery(client, &changed_discovery) {
+                        eprintln!("Error publishing Home Assistant discovery to MQTT: {}", e);
+                    }
+                });
+            }
+            for ecu in ecus.values() {
+                let base_topic = ecu.base_topic(&config.mqtt_base_topic);
+                let state = |signal: &SignalDefinition| {
+                    ecu.vehicle_data
+                        .state(&signal.key, now, stale_after(signal))
+                };
+                if config.mqtt_output.publishes_topics()
+                    && let Err(e) = publish_vehicle_data(
+                        mqtt_session.client(),
+                        &registry,
+                        &ecu.vehicle_data,
+                        &state,
+                        &config.publish_policies,
+                        &base_topic,
+                    )
+                {
+                    eprintln!("Error publishing to MQTT: {}", e);
+                }
+                if let Err(e) =
+                    publish_trouble_codes(mqtt_session.client(), &ecu.trouble_codes, &base_topic)
+                {
+                    eprintln!("Error publishing DTCs to MQTT: {}", e);
+                }
+                if let Err(e) = publish_freeze_frame(
+                    mqtt_session.client(),
+                    &registry,
+                    &ecu.freeze_frame,
+                    &base_topic,
+                ) {
+                    eprintln!("Error publishing freeze frame to MQTT: {}", e);
+                }
+                if let Err(e) =
+                    publish_readiness(mqtt_session.client(), &ecu.readiness, &base_topic)
+                {
+                    eprintln!("Error publishing readiness to MQTT: {}", e);
+                }
+            }
+            if config.mqtt_output.publishes_snapshot()
+                && last_snapshot_publish
+                    .is_none_or(|last| last.elapsed() >= config.snapshot_interval)
+            {
+                let snapshot = vehicle_snapshot(&registry, &ecus, &stale_after, now);
+                if let Err(e) =
+                    publish_event(mqtt_session.client(), &config.snapshot_topic, &snapshot, 0)
+                {
+                    eprintln!("Error publishing snapshot to MQTT: {}", e);
+                }
+                last_snapshot_publish = Some(Instant::now());
+            }
+            if let Err(e) = publish_vehicle_info(mqtt_session.client(), &vehicle_info, &config) {
+                eprintln!("Error publishing vehicle info to MQTT: {}", e);
+            }
+            if let Some(supported) = &supported_pids
+                && let Err(e) = publish_supported_pids(mqtt_session.client(), supported, &config)
+            {
+                eprintln!("Error publishing supported PIDs to MQTT: {}", e);
+            }
+            if last_diagnostics_publish
+                .is_none_or(|last| last.elapsed() >= config.diagnostics_interval)
+            {
+                if let Err(e) = publish_request_stats(mqtt_session.client(), &tracker, &config) {
+                    eprintln!("Error publishing request statistics to MQTT: {}", e);
+                }
+                for ecu in ecus.values() {
+                    if let Err(e) = publish_negative_responses(
+                        mqtt_session.client(),
+                        &ecu.negative_responses,
+                        &ecu.base_topic(&config.mqtt_base_topic),
+                    ) {
+                        eprintln!("Error publishing negative responses to MQTT: {}", e);
+                    }
+                }
+                last_diagnostics_publish = Some(Instant::now());
+            }
+            if let Some(result) = &clear_dtc_result {
+                let topic = format!("{}/result", config.clear_dtc_topic);
+                let payload = result.to_json();
+                // Kept for the next pass while another publish is still being delivered
+                if mqtt_session.publish_in_background(move |client| {
+                    if let Err(e) = publish_event(client, &topic, &payload, 1) {
+                        eprintln!("Error publishing DTC clear result: {}", e);
+                    }
+                }) {
+                    clear_dtc_result = None;
+                }
+            }
+        }
     }
 }
 
@@ -178,254 +509,270 @@ fn load_config_or_exit(config_path: Option<&str>) -> AppConfig {
     }
 }
 
-async fn send_request(socket: &CanSocket, pid: u8) -> std::io::Result<()> {
-    send_obd_request(socket, pid)
-        .await
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+/// Loads the custom PID definitions file, if one is configured, or exits on errors.
+fn load_custom_signals_or_exit(config: &AppConfig) -> Vec<CustomSignal> {
+    let Some(path) = &config.custom_pids_file else {
+        return Vec::new();
+    };
+
+    match load_custom_signals(path, config.custom_pid_rate_hz) {
+        Ok(signals) => signals,
+        Err(err) => {
+            eprintln!("Error loading custom PIDs: {}", err);
+            std::process::exit(1);
+        }
+    }
 }
 
+/// Route a reassembled response payload to the decoder for its service.
+///
+/// Negative responses are logged per ECU and unrelated traffic is ignored.
+///
+/// # Returns
+///
+/// Returns the outcome if the payload answers a mode 04 (clear DTCs) request.
+fn handle_response(
+    payload: &[u8],
+    registry: &SignalRegistry,
+    ecu: &mut EcuState,
+) -> Option<ClearResult> {
+    let (service, data) = match classify(payload) {
+        ResponseKind::Positive { service, data } => (service, data),
+        ResponseKind::Negative(negative) => {
+            // Response-pending only delays the answer, the tracker and UDS client wait for it
+            if negative.nrc != RESPONSE_PENDING {
+                ecu.negative_responses.record(negative);
+            }
+            return parse_clear_response(payload);
+        }
+        ResponseKind::Unrelated => return None,
+    };
+
+    let result = match (service, data.first()) {
+        (0x01, Some(&(PID_MONITOR_STATUS | PID_DRIVE_CYCLE_STATUS))) => {
+            parse_readiness_response(payload, &mut ecu.readiness)
+        }
+        (0x02, _) => parse_freeze_frame_response(payload, registry, &mut ecu.freeze_frame),
+        (0x03 | 0x07 | 0x0A, _) => {
+            parse_dtc_response(payload, &mut ecu.trouble_codes);
+            Ok(())
+        }
+        (0x04, _) => return parse_clear_response(payload),
+        // Mode 01 and custom PIDs of any other service, e.g. 0x22 for UDS ReadDataByIdentifier
+        _ => parse_obd_response(payload, registry, &mut ecu.vehicle_data),
+    };
+    if let Err(e) = result {
+        eprintln!("Error decoding response {:02X?}: {}", payload, e);
+    }
+    None
+}
+
+/// The JSON snapshot of every ECU's signals, see `snapshot_json`.
+fn vehicle_snapshot(
+    registry: &SignalRegistry,
+    ecus: &BTreeMap<Id, EcuState>,
+    stale_after: &dyn Fn(&SignalDefinition) -> Duration,
+    now: Instant,
+) -> String {
+    let mut engine = serde_json::Value::Null;
+    let mut others = Vec::new();
+
+    for ecu in ecus.values() {
+        let state = |signal: &SignalDefinition| {
+            ecu.vehicle_data
+                .state(&signal.key, now, stale_after(signal))
+        };
+        let signals = signals_json(registry, &ecu.vehicle_data, &state);
+        if ecu.is_engine {
+            engine = signals;
+        } else {
+            others.push((id_to_hex(ecu.response_id), signals));
+        }
+    }
+
+    snapshot_json(SystemTime::now(), engine, others)
+}
+
+/// Publish the Home Assistant discovery messages; unchanged configs are only sent once.
+pub fn publish_discovery(
+    cli: &mqtt::Client,
+    messages: &[(String, String)],
+) -> Result<(), Box<dyn Error>> {
+    for (topic, payload) in messages {
+        publish_if_changed(cli, topic, payload, 1)?;
+    }
+
+    Ok(())
+}
+
+/// Publish every signal of the registry under `base_topic`, following each signal's
+/// publish policy.
+///
+/// Signals the ECU never reported are skipped and stale values are replaced by
+/// `UNAVAILABLE`.
 pub fn publish_vehicle_data(
     cli: &mqtt::Client,
+    registry: &SignalRegistry,
     data: &VehicleData,
-    config: &AppConfig,
+    state: &dyn Fn(&SignalDefinition) -> SignalState,
+    policies: &PublishPolicies,
+    base_topic: &str,
 ) -> Result<(), Box<dyn Error>> {
-    let base_topic = &config.mqtt_base_topic;
+    let now = Instant::now();
+    for signal in registry.iter() {
+        let topic = format!("{}/{}", base_topic, signal.topic);
+        let policy = policies.for_signal(&signal.key);
+        match (state(signal), data.get(&signal.key)) {
+            (SignalState::Valid, Some(value)) => {
+                publish_signal(cli, &topic, Some(value), &signal.format(value), policy, now)?
+            }
+            (SignalState::Stale, _) => publish_signal(cli, &topic, None, UNAVAILABLE, policy, now)?,
+            _ => {}
+        }
+    }
 
-    // Engine parameters
-    publish_if_changed(
-        cli,
-        &format!("{}/RPM", base_topic),
-        &data.engine_rpm.to_string(),
-        0,
-    )?;
-    publish_if_changed(
-        cli,
-        &format!("{}/TPS", base_topic),
-        &data.throttle_pos.to_string(),
-        0,
-    )?;
-    publish_if_changed(
-        cli,
-        &format!("{}/MAP", base_topic),
-        &data.intake_pressure.to_string(),
-        0,
-    )?;
-    publish_if_changed(
-        cli,
-        &format!("{}/MAT", base_topic),
-        &data.intake_temp.to_string(),
-        0,
-    )?;
-    publish_if_changed(
-        cli,
-        &format!("{}/CLT", base_topic),
-        &data.coolant_temp.to_string(),
-        0,
-    )?;
-    publish_if_changed(
-        cli,
-        &format!("{}/BAT", base_topic),
-        &data.control_module_voltage.to_string(),
-        0,
-    )?;
-    publish_if_changed(
-        cli,
-        &format!("{}/BAR", base_topic),
-        &data.baro_pressure.to_string(),
-        0,
-    )?;
-    publish_if_changed(
-        cli,
-        &format!("{}/VSS", base_topic),
-        &data.vehicle_speed.to_string(),
-        0,
-    )?;
-    publish_if_changed(
-        cli,
-        &format!("{}/GER", base_topic),
-        &data.actual_gear.to_string(),
-        0,
-    )?;
+    Ok(())
+}
 
-    // Other parameters
-    publish_if_changed(
-        cli,
-        &format!("{}/ELD", base_topic),
-        &data.engine_load.to_string(),
-        0,
-    )?;
-    publish_if_changed(
-        cli,
-        &format!("{}/FST", base_topic),
-        &data.fuel_trim_short_b1.to_string(),
-        0,
-    )?;
-    publish_if_changed(
-        cli,
-        &format!("{}/FLT", base_topic),
-        &data.fuel_trim_long_b1.to_string(),
-        0,
-    )?;
-    publish_if_changed(
-        cli,
-        &format!("{}/FS2", base_topic),
-        &data.fuel_trim_short_b2.to_string(),
-        0,
-    )?;
-    publish_if_changed(
-        cli,
-        &format!("{}/FL2", base_topic),
-        &data.fuel_trim_long_b2.to_string(),
-        0,
-    )?;
-    publish_if_changed(
-        cli,
-        &format!("{}/FPR", base_topic),
-        &data.fuel_pressure.to_string(),
-        0,
-    )?;
-    publish_if_changed(
-        cli,
-        &format!("{}/TAD", base_topic),
-        &data.timing_advance.to_string(),
-        0,
-    )?;
-    publish_if_changed(
-        cli,
-        &format!("{}/MAS", base_topic),
-        &data.maf_sensor.to_string(),
-        0,
-    )?;
+/// Publish each known DTC list as a JSON array, e.g. `["P0133","P0420"]`.
+///
+/// Lists are retained and only republished when their content changes.
+pub fn publish_trouble_codes(
+    cli: &mqtt::Client,
+    codes: &TroubleCodes,
+    base_topic: &str,
+) -> Result<(), Box<dyn Error>> {
+    for kind in DtcKind::ALL {
+        if let Some(list) = codes.get(kind) {
+            publish_if_changed(
+                cli,
+                &format!("{}/{}", base_topic, kind.topic()),
+                &serde_json::to_string(list)?,
+                0,
+            )?;
+        }
+    }
 
-    // O2 Sensors
-    publish_if_changed(
-        cli,
-        &format!("{}/O21", base_topic),
-        &data.o2_sensor_voltage_b1s1.to_string(),
-        0,
-    )?;
-    publish_if_changed(
-        cli,
-        &format!("{}/O22", base_topic),
-        &data.o2_sensor_voltage_b1s2.to_string(),
-        0,
-    )?;
-    publish_if_changed(
-        cli,
-        &format!("{}/O23", base_topic),
-        &data.o2_sensor_voltage_b1s3.to_string(),
-        0,
-    )?;
-    publish_if_changed(
-        cli,
-        &format!("{}/O24", base_topic),
-        &data.o2_sensor_voltage_b1s4.to_string(),
-        0,
-    )?;
+    Ok(())
+}
 
-    // Engine Run Data
-    publish_if_changed(
-        cli,
-        &format!("{}/ERT", base_topic),
-        &data.engine_run_time.to_string(),
-        0,
-    )?;
-    publish_if_changed(
-        cli,
-        &format!("{}/MIL", base_topic),
-        &data.distance_with_mil.to_string(),
-        0,
-    )?;
-    publish_if_changed(
-        cli,
-        &format!("{}/FRL", base_topic),
-        &data.fuel_rail_pressure.to_string(),
-        0,
-    )?;
+/// Publish the readiness report as JSON and the MIL state as `true` or `false` on `mil_on`.
+pub fn publish_readiness(
+    cli: &mqtt::Client,
+    readiness: &Readiness,
+    base_topic: &str,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(report) = readiness.to_json() {
+        publish_if_changed(cli, &format!("{}/readiness", base_topic), &report, 0)?;
+    }
+    if let Some(mil_on) = readiness.mil_on() {
+        publish_if_changed(
+            cli,
+            &format!("{}/mil_on", base_topic),
+            &mil_on.to_string(),
+            0,
+        )?;
+    }
 
-    // EGR Related
-    publish_if_changed(
-        cli,
-        &format!("{}/EGR", base_topic),
-        &data.commanded_egr.to_string(),
-        0,
-    )?;
-    publish_if_changed(
-        cli,
-        &format!("{}/EGE", base_topic),
-        &data.egr_error.to_string(),
-        0,
-    )?;
-    publish_if_changed(
-        cli,
-        &format!("{}/EGT", base_topic),
-        &data.egr_temp.to_string(),
-        0,
-    )?;
+    Ok(())
+}
 
-    // Turbo Related
-    publish_if_changed(
-        cli,
-        &format!("{}/TBR", base_topic),
-        &data.turbo_rpm.to_string(),
-        0,
-    )?;
-    publish_if_changed(
-        cli,
-        &format!("{}/TB1", base_topic),
-        &data.turbo_temp_1.to_string(),
-        0,
-    )?;
-    publish_if_changed(
-        cli,
-        &format!("{}/TB2", base_topic),
-        &data.turbo_temp_2.to_string(),
-        0,
-    )?;
-    publish_if_changed(
-        cli,
-        &format!("{}/CAT", base_topic),
-        &data.charge_air_temp.to_string(),
-        0,
-    )?;
+/// Publish the freeze frame as a JSON object tied to the DTC that caused it.
+pub fn publish_freeze_frame(
+    cli: &mqtt::Client,
+    registry: &SignalRegistry,
+    freeze_frame: &FreezeFrame,
+    base_topic: &str,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(report) = freeze_frame.to_json(registry) {
+        publish_if_changed(cli, &format!("{}/freeze_frame", base_topic), &report, 0)?;
+    }
 
-    // Additional Parameters
-    publish_if_changed(
-        cli,
-        &format!("{}/ETH", base_topic),
-        &data.ethanol_fuel.to_string(),
-        0,
-    )?;
-    publish_if_changed(
-        cli,
-        &format!("{}/OIT", base_topic),
-        &data.engine_oil_temp.to_string(),
-        0,
-    )?;
-    publish_if_changed(
-        cli,
-        &format!("{}/FRT", base_topic),
-        &data.engine_fuel_rate.to_string(),
-        0,
-    )?;
-    publish_if_changed(
-        cli,
-        &format!("{}/FRM", base_topic),
-        &data.fuel_rate_mg.to_string(),
-        0,
-    )?;
+    Ok(())
+}
+
+/// Publish the per-request latency, timeout and success statistics of the engine ECU.
+pub fn publish_request_stats(
+    cli: &mqtt::Client,
+    tracker: &RequestTracker,
+    config: &AppConfig,
+) -> Result<(), Box<dyn Error>> {
     publish_if_changed(
         cli,
-        &format!("{}/DEF", base_topic),
-        &data.def_dosing.to_string(),
+        &format!("{}/diagnostics/requests", config.mqtt_base_topic),
+        &tracker.to_json(),
         0,
     )?;
+
+    Ok(())
+}
+
+/// Publish how often the ECU answered with each negative response code, with its name.
+pub fn publish_negative_responses(
+    cli: &mqtt::Client,
+    log: &NegativeResponseLog,
+    base_topic: &str,
+) -> Result<(), Box<dyn Error>> {
+    if !log.is_empty() {
+        publish_if_changed(
+            cli,
+            &format!("{}/diagnostics/nrc", base_topic),
+            &log.to_json(),
+            0,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Publish the PIDs supported by the engine ECU as a JSON array of hex strings, e.g. `["04","0C"]`.
+pub fn publish_supported_pids(
+    cli: &mqtt::Client,
+    supported: &BTreeSet<u8>,
+    config: &AppConfig,
+) -> Result<(), Box<dyn Error>> {
+    let pids: Vec<String> = supported.iter().map(|pid| format!("{:02X}", pid)).collect();
+
     publish_if_changed(
         cli,
-        &format!("{}/ODO", base_topic),
-        &data.odometer.to_string(),
+        &format!("{}/info/supported_pids", config.mqtt_base_topic),
+        &serde_json::to_string(&pids)?,
         0,
     )?;
 
     Ok(())
 }
+
+/// Publish the vehicle identity read through mode 09 under `<base>/info/`.
+pub fn publish_vehicle_info(
+    cli: &mqtt::Client,
+    info: &VehicleInfo,
+    config: &AppConfig,
+) -> Result<(), Box<dyn Error>> {
+    let base_topic = &config.mqtt_base_topic;
+
+    if let Some(vin) = &info.vin {
+        publish_if_changed(cli, &format!("{}/info/vin", base_topic), vin, 0)?;
+    }
+    if let Some(ecu_name) = &info.ecu_name {
+        publish_if_changed(cli, &format!("{}/info/ecu_name", base_topic), ecu_name, 0)?;
+    }
+    if !info.calibration_ids.is_empty() {
+        publish_if_changed(
+            cli,
+            &format!("{}/info/calibration_ids", base_topic),
+            &serde_json::to_string(&info.calibration_ids)?,
+            0,
+        )?;
+    }
+    if !info.cvns.is_empty() {
+        publish_if_changed(
+            cli,
+            &format!("{}/info/cvns", base_topic),
+            &serde_json::to_string(&info.cvns)?,
+            0,
+        )?;
+    }
+
+    Ok(())
+}
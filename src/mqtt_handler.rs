@@ -1,12 +1,82 @@
-use log::{debug, error};
+use log::debug;
 use paho_mqtt as mqtt;
 use std::collections::HashMap;
 use std::sync::Mutex;
-use std::{process, time::Duration};
+use std::time::{Duration, Instant};
 use thiserror::Error;
+use tokio::sync::oneshot;
 
 lazy_static::lazy_static! {
     static ref LAST_VALUES: Mutex<HashMap<String, String>> = Mutex::new(HashMap::new());
+    static ref LAST_SIGNALS: Mutex<HashMap<String, PublishedSignal>> = Mutex::new(HashMap::new());
+}
+
+/// When a signal value is published again.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PublishPolicy {
+    /// Smallest absolute change from the last published value that is published.
+    pub deadband: f64,
+    /// Smallest change, in percent of the last published value, that is published.
+    pub deadband_percent: f64,
+    /// A signal is never published more often than this.
+    pub min_interval: Duration,
+    /// An unchanged value is republished after this long, if set.
+    pub heartbeat: Option<Duration>,
+}
+
+/// The default publish policy and per-signal overrides.
+#[derive(Debug, Clone, Default)]
+pub struct PublishPolicies {
+    pub default: PublishPolicy,
+    /// Keyed by signal key, e.g. `maf_sensor`.
+    pub signals: HashMap<String, PublishPolicy>,
+}
+
+impl PublishPolicies {
+    /// The policy of the signal `key`.
+    pub fn for_signal(&self, key: &str) -> &PublishPolicy {
+        self.signals.get(key).unwrap_or(&self.default)
+    }
+}
+
+/// What was last published to a signal topic.
+struct PublishedSignal {
+    value: Option<f64>,
+    payload: String,
+    at: Instant,
+}
+
+impl PublishPolicy {
+    /// Whether a new payload should replace `last`.
+    ///
+    /// The minimum interval always wins, the heartbeat forces a republish, and otherwise a
+    /// numeric value must leave both deadbands around the last published value.
+    fn should_publish(
+        &self,
+        last: &PublishedSignal,
+        value: Option<f64>,
+        payload: &str,
+        now: Instant,
+    ) -> bool {
+        let elapsed = now.saturating_duration_since(last.at);
+        if elapsed < self.min_interval {
+            return false;
+        }
+        if self.heartbeat.is_some_and(|heartbeat| elapsed >= heartbeat) {
+            return true;
+        }
+        if payload == last.payload {
+            return false;
+        }
+
+        match (value, last.value) {
+            (Some(value), Some(last)) => {
+                let change = (value - last).abs();
+                change > self.deadband && change > last.abs() * self.deadband_percent / 100.0
+            }
+            _ => true,
+        }
+    }
 }
 
 #[derive(Error, Debug)]
@@ -23,44 +93,263 @@ pub enum PublishError {
 
 use crate::config::AppConfig;
 
-/// Set up and return an MQTT client based on the provided configuration.
-///
-/// This function takes an `AppConfig` reference, extracts MQTT-related information
-/// (host and port) from it, creates an MQTT client, sets a timeout, and attempts to connect to the broker.
-///
-/// # Arguments
-///
-/// * `config` - A reference to the `AppConfig` struct containing MQTT configuration information.
-///
-/// # Panics
-///
-/// Panics if there is an error creating the MQTT client or if it fails to connect to the broker.
+/// Shortest delay between two connection attempts.
+const RECONNECT_MIN_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Longest delay between two connection attempts; the backoff stops doubling here.
+const RECONNECT_MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Retained on `<base>/status` after every connect.
+pub const STATUS_ONLINE: &str = "online";
+
+/// Last Will: published by the broker on `<base>/status` when the connection drops.
+pub const STATUS_OFFLINE: &str = "offline";
+
+/// A long-lived MQTT connection shared by the whole polling loop.
 ///
-/// # Returns
+/// The session owns a single `mqtt::Client` and (re)connects it lazily from
+/// `ensure_connected`. Connection attempts run on a blocking thread, so an unreachable
+/// broker never stalls the CAN loop. Failed attempts are retried with an exponential
+/// backoff so a broker outage neither terminates the bridge nor floods the broker with
+/// connection requests.
 ///
-/// Returns an MQTT client upon successful setup and connection.
-pub fn setup_mqtt(config: &AppConfig) -> mqtt::Client {
-    // Format the MQTT broker host and port.
-    let host = format!("mqtt://{}:{}", config.mqtt_host, config.mqtt_port);
+/// `<base>/status` tells consumers whether the data is live: the session publishes
+/// `online` on every connect and registers `offline` as its Last Will.
+pub struct MqttSession {
+    client: mqtt::Client,
+    conn_opts: mqtt::ConnectOptions,
+    status_topic: String,
+    backoff: Duration,
+    next_attempt: Instant,
+    was_connected: bool,
+    subscriptions: Vec<String>,
+    messages: mqtt::Receiver<Option<mqtt::Message>>,
+    /// The outcome of the connection attempt in progress, if any.
+    connecting: Option<oneshot::Receiver<Result<(), String>>>,
+    /// The publish started by `publish_in_background`, if any.
+    background: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl MqttSession {
+    /// Create a session for the broker described in `config`.
+    ///
+    /// No connection is attempted here; call `ensure_connected` before publishing.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - A reference to the `AppConfig` struct containing MQTT configuration information.
+    ///
+    /// # Returns
+    ///
+    /// Returns the session, or an error if the client could not be created (e.g. an invalid URI).
+    pub fn new(config: &AppConfig) -> Result<Self, PublishError> {
+        // Format the MQTT broker host and port.
+        let scheme = if config.mqtt_tls { "mqtts" } else { "mqtt" };
+        let host = format!("{}://{}:{}", scheme, config.mqtt_host, config.mqtt_port);
+
+        let create_opts = mqtt::CreateOptionsBuilder::new()
+            .server_uri(host)
+            .client_id(config.mqtt_client_id.as_str())
+            .finalize();
+        let mut client = mqtt::Client::new(create_opts)?;
+
+        // Set a timeout of 5 seconds for synchronous calls.
+        client.set_timeout(Duration::from_secs(5));
+
+        let status_topic = format!("{}/status", config.mqtt_base_topic);
+        let mut conn_builder = mqtt::ConnectOptionsBuilder::new();
+        conn_builder
+            .connect_timeout(Duration::from_secs(5))
+            .keep_alive_interval(Duration::from_secs(20))
+            .clean_session(true)
+            .will_message(mqtt::Message::new_retained(
+                status_topic.as_str(),
+                STATUS_OFFLINE,
+                1,
+            ));
+        if let Some(username) = &config.mqtt_username {
+            conn_builder.user_name(username.as_str());
+        }
+        if let Some(password) = &config.mqtt_password {
+            conn_builder.password(password.as_str());
+        }
+        if config.mqtt_tls {
+            conn_builder.ssl_options(ssl_options(config)?);
+        }
+        let conn_opts = conn_builder.finalize();
+
+        // Start consuming before connecting so no incoming message is lost.
+        let messages = client.start_consuming();
+
+        Ok(MqttSession {
+            client,
+            conn_opts,
+            status_topic,
+            backoff: RECONNECT_MIN_BACKOFF,
+            next_attempt: Instant::now(),
+            was_connected: false,
+            subscriptions: Vec::new(),
+            messages,
+            connecting: None,
+            background: None,
+        })
+    }
 
-    // Create an MQTT client.
-    let mut cli = mqtt::Client::new(host).unwrap_or_else(|e| {
-        // Print an error message and exit the program if client creation fails.
-        println!("Error creating the client: {:?}", e);
-        process::exit(1);
-    });
+    /// Make sure the client is connected, starting a (re)connect if the backoff allows it.
+    ///
+    /// This never blocks and never terminates the process: the connect, the birth message
+    /// and the subscriptions run on a blocking thread and their outcome is picked up by a
+    /// later call. While the broker is unreachable the delay between attempts doubles,
+    /// up to `RECONNECT_MAX_BACKOFF`.
+    ///
+    /// # Returns
+    ///
+    /// Returns `true` if the client is connected and messages can be published.
+    pub fn ensure_connected(&mut self) -> bool {
+        if let Some(connecting) = &mut self.connecting {
+            let result = match connecting.try_recv() {
+                Err(oneshot::error::TryRecvError::Empty) => return false,
+                Err(oneshot::error::TryRecvError::Closed) => {
+                    Err("connection attempt aborted".to_string())
+                }
+                Ok(result) => result,
+            };
+            self.connecting = None;
+            self.connect_finished(result);
+        }
 
-    // Set a timeout of 5 seconds for synchronous calls.
-    cli.set_timeout(Duration::from_secs(5));
+        if self.client.is_connected() {
+            return true;
+        }
 
-    // Attempt to connect to the MQTT broker and exit the program if the connection fails.
-    if let Err(e) = cli.connect(None) {
-        println!("Unable to connect: {:?}", e);
-        process::exit(1);
+        if self.was_connected {
+            eprintln!("Lost connection to MQTT broker");
+            self.was_connected = false;
+        }
+
+        if Instant::now() >= self.next_attempt {
+            self.start_connect();
+        }
+        false
     }
 
-    // Return the configured and connected MQTT client.
-    cli
+    /// Connect on a blocking thread, then publish the birth message and restore the
+    /// subscriptions there too, since the synchronous client waits for their acknowledgements.
+    fn start_connect(&mut self) {
+        let client = self.client.clone();
+        let conn_opts = self.conn_opts.clone();
+        let status_topic = self.status_topic.clone();
+        let subscriptions = self.subscriptions.clone();
+        let (result_tx, result_rx) = oneshot::channel();
+
+        tokio::task::spawn_blocking(move || {
+            let result = client.connect(conn_opts).map(|_| {
+                // Replace the Last Will the broker may have published while we were away.
+                let birth = mqtt::Message::new_retained(status_topic.as_str(), STATUS_ONLINE, 1);
+                if let Err(e) = client.publish(birth) {
+                    eprintln!("Unable to publish {}: {}", status_topic, e);
+                }
+                // Clean sessions drop subscriptions, so restore them on every connect.
+                for topic in &subscriptions {
+                    if let Err(e) = client.subscribe(topic, 1) {
+                        eprintln!("Unable to subscribe to {}: {}", topic, e);
+                    }
+                }
+            });
+            // The session may be gone by now; then nobody needs the outcome.
+            let _ = result_tx.send(result.map_err(|e| e.to_string()));
+        });
+        self.connecting = Some(result_rx);
+    }
+
+    fn connect_finished(&mut self, result: Result<(), String>) {
+        match result {
+            Ok(()) => {
+                eprintln!("Connected to MQTT broker");
+                self.was_connected = true;
+                self.backoff = RECONNECT_MIN_BACKOFF;
+                // The broker may have lost retained values while we were away.
+                forget_published_values();
+            }
+            Err(e) => {
+                eprintln!(
+                    "Unable to connect to MQTT broker: {}, retrying in {:?}",
+                    e, self.backoff
+                );
+                self.next_attempt = Instant::now() + self.backoff;
+                self.backoff = (self.backoff * 2).min(RECONNECT_MAX_BACKOFF);
+            }
+        }
+    }
+
+    /// Subscribe to `topic`, now if connected and again after every reconnect.
+    pub fn subscribe(&mut self, topic: &str) {
+        self.subscriptions.push(topic.to_string());
+        if self.client.is_connected()
+            && let Err(e) = self.client.subscribe(topic, 1)
+        {
+            eprintln!("Unable to subscribe to {}: {}", topic, e);
+        }
+    }
+
+    /// Take the next message received on a subscribed topic, without blocking.
+    pub fn poll_message(&self) -> Option<mqtt::Message> {
+        // `None` entries only signal a lost connection, which `ensure_connected` handles.
+        self.messages.try_iter().flatten().next()
+    }
+
+    /// Run `publish` with the client on a blocking thread, for QoS 1 messages whose
+    /// acknowledgement could otherwise stall the caller while the broker is slow.
+    ///
+    /// # Returns
+    ///
+    /// Returns `false` without running `publish` while the previous one is still running,
+    /// so the caller can retry later.
+    pub fn publish_in_background(
+        &mut self,
+        publish: impl FnOnce(&mqtt::Client) + Send + 'static,
+    ) -> bool {
+        if self
+            .background
+            .as_ref()
+            .is_some_and(|task| !task.is_finished())
+        {
+            return false;
+        }
+        let client = self.client.clone();
+        self.background = Some(tokio::task::spawn_blocking(move || publish(&client)));
+        true
+    }
+
+    /// Access the underlying MQTT client.
+    pub fn client(&self) -> &mqtt::Client {
+        &self.client
+    }
+}
+
+/// Build the TLS options from the certificate files in `config`.
+fn ssl_options(config: &AppConfig) -> Result<mqtt::SslOptions, PublishError> {
+    let mut ssl = mqtt::SslOptionsBuilder::new();
+    if let Some(ca_file) = &config.mqtt_ca_file {
+        ssl.trust_store(ca_file)?;
+    }
+    if let Some(cert_file) = &config.mqtt_client_cert_file {
+        ssl.key_store(cert_file)?;
+    }
+    if let Some(key_file) = &config.mqtt_client_key_file {
+        ssl.private_key(key_file)?;
+    }
+    Ok(ssl.finalize())
+}
+
+/// Forget every value remembered by `publish_if_changed`, so the next cycle republishes all topics.
+fn forget_published_values() {
+    if let Ok(mut last_values) = LAST_VALUES.lock() {
+        last_values.clear();
+    }
+    if let Ok(mut last_signals) = LAST_SIGNALS.lock() {
+        last_signals.clear();
+    }
 }
 
 /// Publish an MQTT message only if the value has changed since last publication
@@ -90,14 +379,9 @@ pub fn publish_if_changed(
         return Err(PublishError::InvalidQoS);
     }
 
-    // Get lock on last values
-    let mut last_values = LAST_VALUES.lock().map_err(|_| PublishError::LockError)?;
-
-    // Check if value has changed
-    if last_values
-        .get(topic)
-        .map_or(true, |last_value| last_value != payload)
-    {
+    // Check if value has changed; the lock is not held while publishing, so a slow
+    // broker can't block publishes from other threads
+    if has_changed(topic, payload)? {
         debug!("Publishing changed value to topic: {}", topic);
 
         // Create and publish message
@@ -111,7 +395,10 @@ pub fn publish_if_changed(
         cli.publish(msg).map_err(PublishError::MqttError)?;
 
         // Update stored value after successful publish
-        last_values.insert(topic.to_string(), payload.to_string());
+        LAST_VALUES
+            .lock()
+            .map_err(|_| PublishError::LockError)?
+            .insert(topic.to_string(), payload.to_string());
 
         Ok(())
     } else {
@@ -120,6 +407,69 @@ pub fn publish_if_changed(
     }
 }
 
+/// Whether `payload` differs from what `publish_if_changed` last published to `topic`.
+pub fn has_changed(topic: &str, payload: &str) -> Result<bool, PublishError> {
+    let last_values = LAST_VALUES.lock().map_err(|_| PublishError::LockError)?;
+    Ok(last_values
+        .get(topic)
+        .is_none_or(|last_value| last_value != payload))
+}
+
+/// Publish a signal value as a retained message if `policy` allows it.
+///
+/// # Arguments
+///
+/// * `cli` - A reference to the MQTT client.
+/// * `topic` - The signal's topic.
+/// * `value` - The numeric value, or `None` for payloads such as `unavailable`.
+/// * `payload` - The formatted value.
+/// * `policy` - Deadbands and intervals of the signal.
+/// * `now` - The current time.
+///
+/// # Returns
+///
+/// Returns `Result<(), PublishError>` indicating success or if an error occurred
+pub fn publish_signal(
+    cli: &mqtt::Client,
+    topic: &str,
+    value: Option<f64>,
+    payload: &str,
+    policy: &PublishPolicy,
+    now: Instant,
+) -> Result<(), PublishError> {
+    if topic.is_empty() || payload.is_empty() {
+        return Err(PublishError::EmptyInput);
+    }
+
+    let mut last_signals = LAST_SIGNALS.lock().map_err(|_| PublishError::LockError)?;
+
+    if last_signals
+        .get(topic)
+        .is_some_and(|last| !policy.should_publish(last, value, payload, now))
+    {
+        debug!(
+            "Skipping publish - value within policy for topic: {}",
+            topic
+        );
+        return Ok(());
+    }
+
+    debug!("Publishing signal value to topic: {}", topic);
+    let msg = mqtt::Message::new_retained(topic, payload, 0);
+    cli.publish(msg).map_err(PublishError::MqttError)?;
+
+    last_signals.insert(
+        topic.to_string(),
+        PublishedSignal {
+            value,
+            payload: payload.to_string(),
+            at: now,
+        },
+    );
+
+    Ok(())
+}
+
 /// Publish an MQTT message to the specified topic with the given payload and QoS.
 ///
 /// # Arguments
@@ -141,3 +491,161 @@ pub fn publish_message(
     // For backwards compatibility, this now calls publish_if_changed
     publish_if_changed(cli, topic, payload, qos)
 }
+
+/// Publish a one-off, non-retained MQTT message such as a command result.
+///
+/// Unlike `publish_if_changed`, the message is always sent, even if its payload repeats.
+///
+/// # Arguments
+///
+/// * `cli` - A reference to the MQTT client.
+/// * `topic` - The MQTT topic to which the message will be published.
+/// * `payload` - The payload of the MQTT message.
+/// * `qos` - The Quality of Service level for the message.
+///
+/// # Returns
+///
+/// Returns `Result<(), PublishError>` indicating success or failure.
+pub fn publish_event(
+    cli: &mqtt::Client,
+    topic: &str,
+    payload: &str,
+    qos: i32,
+) -> Result<(), PublishError> {
+    if topic.is_empty() || payload.is_empty() {
+        return Err(PublishError::EmptyInput);
+    }
+
+    if qos > 2 {
+        return Err(PublishError::InvalidQoS);
+    }
+
+    let msg = mqtt::Message::new(topic, payload, qos);
+    cli.publish(msg).map_err(PublishError::MqttError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn published(value: f64, at: Instant) -> PublishedSignal {
+        PublishedSignal {
+            value: Some(value),
+            payload: format!("{:.1}", value),
+            at,
+        }
+    }
+
+    fn should_publish(
+        policy: &PublishPolicy,
+        last: &PublishedSignal,
+        value: f64,
+        now: Instant,
+    ) -> bool {
+        policy.should_publish(last, Some(value), &format!("{:.1}", value), now)
+    }
+
+    #[test]
+    fn equal_payloads_are_not_republished() {
+        let policy = PublishPolicy::default();
+        let now = Instant::now();
+        let last = published(20.0, now);
+
+        assert!(!should_publish(
+            &policy,
+            &last,
+            20.0,
+            now + Duration::from_secs(1)
+        ));
+        assert!(should_publish(
+            &policy,
+            &last,
+            20.1,
+            now + Duration::from_secs(1)
+        ));
+        // Non-numeric payloads only need to differ
+        assert!(policy.should_publish(&last, None, "error", now));
+    }
+
+    #[test]
+    fn min_interval_wins_over_changes_and_heartbeat() {
+        let policy = PublishPolicy {
+            min_interval: Duration::from_millis(500),
+            heartbeat: Some(Duration::ZERO),
+            ..PublishPolicy::default()
+        };
+        let now = Instant::now();
+        let last = published(20.0, now);
+
+        assert!(!should_publish(
+            &policy,
+            &last,
+            90.0,
+            now + Duration::from_millis(499)
+        ));
+        assert!(should_publish(
+            &policy,
+            &last,
+            90.0,
+            now + Duration::from_millis(500)
+        ));
+    }
+
+    #[test]
+    fn heartbeat_republishes_unchanged_values() {
+        let policy = PublishPolicy {
+            heartbeat: Some(Duration::from_secs(10)),
+            deadband: 5.0,
+            ..PublishPolicy::default()
+        };
+        let now = Instant::now();
+        let last = published(20.0, now);
+
+        assert!(!should_publish(
+            &policy,
+            &last,
+            20.0,
+            now + Duration::from_secs(9)
+        ));
+        assert!(should_publish(
+            &policy,
+            &last,
+            20.0,
+            now + Duration::from_secs(10)
+        ));
+        assert!(should_publish(
+            &policy,
+            &last,
+            21.0,
+            now + Duration::from_secs(10)
+        ));
+    }
+
+    #[test]
+    fn changes_must_leave_both_deadbands() {
+        let later = Instant::now() + Duration::from_secs(1);
+        let last = published(100.0, Instant::now());
+
+        let absolute = PublishPolicy {
+            deadband: 2.0,
+            ..PublishPolicy::default()
+        };
+        assert!(!should_publish(&absolute, &last, 102.0, later));
+        assert!(should_publish(&absolute, &last, 97.5, later));
+
+        let percent = PublishPolicy {
+            deadband_percent: 5.0,
+            ..PublishPolicy::default()
+        };
+        assert!(!should_publish(&percent, &last, 104.0, later));
+        assert!(should_publish(&percent, &last, 106.0, later));
+
+        let both = PublishPolicy {
+            deadband: 10.0,
+            deadband_percent: 5.0,
+            ..PublishPolicy::default()
+        };
+        assert!(!should_publish(&both, &last, 106.0, later));
+        assert!(should_publish(&both, &last, 111.0, later));
+    }
+}
@@ -0,0 +1,240 @@
+// CAN identifier schemes for OBD on ISO 15765-4: 11-bit or 29-bit, functional or physical.
+
+use crate::constants::{
+    OBD_ENGINE_ADDRESS, OBD_ENGINE_REQUEST_ID, OBD_FUNCTIONAL_ID_29BIT, OBD_PHYSICAL_ID_29BIT,
+    OBD_REQUEST_ID, OBD_RESPONSE_ID, OBD_RESPONSE_ID_LAST, OBD_TESTER_ADDRESS,
+};
+use socketcan::embedded_can::{ExtendedId, Id, StandardId};
+
+/// How requests are addressed and which identifiers carry the responses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Addressing {
+    /// Requests to 0x7DF, responses on 0x7E8-0x7EF.
+    #[default]
+    Functional11Bit,
+    /// Requests to the engine ECU at 0x7E0, responses on 0x7E8.
+    Physical11Bit,
+    /// Requests to 0x18DB33F1, responses on 0x18DAF1xx.
+    Functional29Bit,
+    /// Requests to the engine ECU at 0x18DA10F1, responses on 0x18DAF110.
+    Physical29Bit,
+}
+
+impl Addressing {
+    /// Parse the `obd_addressing` configuration value.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "11bit_functional" => Some(Addressing::Functional11Bit),
+            "11bit_physical" => Some(Addressing::Physical11Bit),
+            "29bit_functional" => Some(Addressing::Functional29Bit),
+            "29bit_physical" => Some(Addressing::Physical29Bit),
+            _ => None,
+        }
+    }
+
+    fn is_extended(self) -> bool {
+        matches!(
+            self,
+            Addressing::Functional29Bit | Addressing::Physical29Bit
+        )
+    }
+
+    /// The identifier regular (polling) requests are sent to.
+    pub fn request_id(self) -> Id {
+        match self {
+            Addressing::Functional11Bit => standard_id(OBD_REQUEST_ID),
+            Addressing::Functional29Bit => extended_id(OBD_FUNCTIONAL_ID_29BIT),
+            Addressing::Physical11Bit | Addressing::Physical29Bit => self.engine_request_id(),
+        }
+    }
+
+    /// The identifier the engine ECU answers on; its data is published as the default.
+    pub fn engine_response_id(self) -> Id {
+        if self.is_extended() {
+            extended_id(OBD_PHYSICAL_ID_29BIT | (OBD_TESTER_ADDRESS << 8) | OBD_ENGINE_ADDRESS)
+        } else {
+            standard_id(OBD_RESPONSE_ID)
+        }
+    }
+
+    /// The physical request identifier of the engine ECU.
+    pub fn engine_request_id(self) -> Id {
+        self.physical_request_id(self.engine_response_id())
+            .expect("the engine ECU has a physical request ID")
+    }
+
+    /// Returns `true` if `id` carries OBD responses under this scheme.
+    pub fn is_response_id(self, id: Id) -> bool {
+        match id {
+            Id::Standard(id) if !self.is_extended() => {
+                (OBD_RESPONSE_ID..=OBD_RESPONSE_ID_LAST).contains(&id.as_raw())
+            }
+            Id::Extended(id) if self.is_extended() => {
+                id.as_raw() & 0xFFFF_FF00 == OBD_PHYSICAL_ID_29BIT | (OBD_TESTER_ADDRESS << 8)
+            }
+            _ => false,
+        }
+    }
+
+    /// The physical request identifier of the ECU answering on `response_id`.
+    ///
+    /// This is also where flow control frames for that ECU's multi-frame responses go.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` for 11-bit response IDs below 8, which have no request ID 8 below them.
+    pub fn physical_request_id(self, response_id: Id) -> Option<Id> {
+        match response_id {
+            Id::Standard(id) => id
+                .as_raw()
+                .checked_sub(OBD_RESPONSE_ID - OBD_ENGINE_REQUEST_ID)
+                .map(standard_id),
+            Id::Extended(id) => {
+                let ecu_address = id.as_raw() & 0xFF;
+                Some(extended_id(
+                    OBD_PHYSICAL_ID_29BIT | (ecu_address << 8) | OBD_TESTER_ADDRESS,
+                ))
+            }
+        }
+    }
+}
+
+/// Format an identifier the way it appears in per-ECU topics, e.g. `7E9` or `18DAF118`.
+pub fn id_to_hex(id: Id) -> String {
+    match id {
+        Id::Standard(id) => format!("{:03X}", id.as_raw()),
+        Id::Extended(id) => format!("{:08X}", id.as_raw()),
+    }
+}
+
+/// The identifier an ECU requested physically on `request_id` answers on: 8 above the
+/// request for 11-bit IDs, e.g. 0x7E8 for 0x7E0, and `18DAF1xx` for `18DAxxF1`.
+///
+/// # Returns
+///
+/// Returns `None` if `request_id` does not follow either scheme.
+pub fn physical_response_id(request_id: Id) -> Option<Id> {
+    match request_id {
+        Id::Standard(id) => id
+            .as_raw()
+            .checked_add(OBD_RESPONSE_ID - OBD_ENGINE_REQUEST_ID)
+            .and_then(StandardId::new)
+            .map(Id::Standard),
+        Id::Extended(id) => {
+            let raw = id.as_raw();
+            (raw & 0xFFFF_00FF == OBD_PHYSICAL_ID_29BIT | OBD_TESTER_ADDRESS).then(|| {
+                let ecu_address = (raw >> 8) & 0xFF;
+                extended_id(OBD_PHYSICAL_ID_29BIT | (OBD_TESTER_ADDRESS << 8) | ecu_address)
+            })
+        }
+    }
+}
+
+/// Turn a raw header from a PID definition into an identifier: 11-bit up to 0x7FF,
+/// 29-bit above.
+pub fn header_id(raw: u32) -> Option<Id> {
+    match u16::try_from(raw) {
+        Ok(raw) if raw <= StandardId::MAX.as_raw() => StandardId::new(raw).map(Id::Standard),
+        _ => ExtendedId::new(raw).map(Id::Extended),
+    }
+}
+
+fn standard_id(raw: u16) -> Id {
+    Id::Standard(StandardId::new(raw).expect("OBD identifiers fit in 11 bits"))
+}
+
+fn extended_id(raw: u32) -> Id {
+    Id::Extended(ExtendedId::new(raw).expect("OBD identifiers fit in 29 bits"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_use_the_configured_scheme() {
+        for (addressing, request_id, engine_response_id) in [
+            (
+                Addressing::Functional11Bit,
+                standard_id(0x7DF),
+                standard_id(0x7E8),
+            ),
+            (
+                Addressing::Physical11Bit,
+                standard_id(0x7E0),
+                standard_id(0x7E8),
+            ),
+            (
+                Addressing::Functional29Bit,
+                extended_id(0x18DB33F1),
+                extended_id(0x18DAF110),
+            ),
+            (
+                Addressing::Physical29Bit,
+                extended_id(0x18DA10F1),
+                extended_id(0x18DAF110),
+            ),
+        ] {
+            assert_eq!(addressing.request_id(), request_id, "{:?}", addressing);
+            assert_eq!(addressing.engine_response_id(), engine_response_id);
+        }
+        assert_eq!(
+            Addressing::parse("29bit_physical"),
+            Some(Addressing::Physical29Bit)
+        );
+        assert_eq!(Addressing::parse("29bit"), None);
+    }
+
+    #[test]
+    fn response_ids_match_the_identifier_width() {
+        let addressing = Addressing::Functional11Bit;
+        assert!(addressing.is_response_id(standard_id(0x7E8)));
+        assert!(addressing.is_response_id(standard_id(0x7EF)));
+        assert!(!addressing.is_response_id(standard_id(0x7E0)));
+        assert!(!addressing.is_response_id(standard_id(0x7F0)));
+        assert!(!addressing.is_response_id(extended_id(0x18DAF110)));
+
+        let addressing = Addressing::Physical29Bit;
+        assert!(addressing.is_response_id(extended_id(0x18DAF110)));
+        assert!(addressing.is_response_id(extended_id(0x18DAF118)));
+        assert!(!addressing.is_response_id(extended_id(0x18DA10F1)));
+        assert!(!addressing.is_response_id(extended_id(0x18DBF110)));
+        assert!(!addressing.is_response_id(standard_id(0x7E8)));
+    }
+
+    #[test]
+    fn physical_ids_are_derived_both_ways() {
+        let addressing = Addressing::Functional11Bit;
+        assert_eq!(
+            addressing.physical_request_id(standard_id(0x7E9)),
+            Some(standard_id(0x7E1))
+        );
+        assert_eq!(addressing.physical_request_id(standard_id(0x007)), None);
+        assert_eq!(
+            Addressing::Functional29Bit.physical_request_id(extended_id(0x18DAF118)),
+            Some(extended_id(0x18DA18F1))
+        );
+
+        assert_eq!(
+            physical_response_id(standard_id(0x714)),
+            Some(standard_id(0x71C))
+        );
+        assert_eq!(physical_response_id(standard_id(0x7F8)), None);
+        assert_eq!(
+            physical_response_id(extended_id(0x18DA18F1)),
+            Some(extended_id(0x18DAF118))
+        );
+        assert_eq!(physical_response_id(extended_id(0x18DB33F1)), None);
+    }
+
+    #[test]
+    fn header_width_follows_the_value() {
+        assert_eq!(header_id(0x7E0), Some(standard_id(0x7E0)));
+        assert_eq!(header_id(0x7FF), Some(standard_id(0x7FF)));
+        assert_eq!(header_id(0x800), Some(extended_id(0x800)));
+        assert_eq!(header_id(0x18DA10F1), Some(extended_id(0x18DA10F1)));
+        assert_eq!(header_id(0x2000_0000), None);
+        assert_eq!(id_to_hex(standard_id(0x7E9)), "7E9");
+        assert_eq!(id_to_hex(extended_id(0x18DAF118)), "18DAF118");
+    }
+}
@@ -0,0 +1,185 @@
+// Response classification: positive responses, negative responses with their decoded
+// response codes (ISO 14229-1 NRCs), and unrelated traffic on the response IDs.
+
+use serde_json::{Map, Value, json};
+use std::collections::BTreeMap;
+
+/// Offset between a request's service ID and its positive response, e.g. 0x01 → 0x41.
+const POSITIVE_RESPONSE_OFFSET: u8 = 0x40;
+
+/// Service byte of a negative response.
+const NEGATIVE_RESPONSE: u8 = 0x7F;
+
+/// A negative response: the rejected service and why.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NegativeResponse {
+    pub service: u8,
+    pub nrc: u8,
+}
+
+impl NegativeResponse {
+    /// The name of the response code, e.g. `requestOutOfRange`.
+    pub fn name(&self) -> &'static str {
+        nrc_name(self.nrc)
+    }
+}
+
+/// What a reassembled payload on a response ID is.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ResponseKind<'a> {
+    /// A positive response to `service`, e.g. service 0x01 for a payload starting with 0x41;
+    /// `data` follows the service byte.
+    Positive {
+        service: u8,
+        data: &'a [u8],
+    },
+    Negative(NegativeResponse),
+    /// Anything else, e.g. an empty payload or a request from another tester.
+    Unrelated,
+}
+
+/// Classify a reassembled ISO-TP payload by its service byte.
+pub fn classify(payload: &[u8]) -> ResponseKind<'_> {
+    match payload {
+        [NEGATIVE_RESPONSE, service, nrc, ..] => ResponseKind::Negative(NegativeResponse {
+            service: *service,
+            nrc: *nrc,
+        }),
+        [service @ 0x40..=0x7E, data @ ..] => ResponseKind::Positive {
+            service: service - POSITIVE_RESPONSE_OFFSET,
+            data,
+        },
+        _ => ResponseKind::Unrelated,
+    }
+}
+
+/// The ISO 14229-1 name of a negative response code.
+pub fn nrc_name(nrc: u8) -> &'static str {
+    match nrc {
+        0x10 => "generalReject",
+        0x11 => "serviceNotSupported",
+        0x12 => "subFunctionNotSupported",
+        0x13 => "incorrectMessageLengthOrInvalidFormat",
+        0x14 => "responseTooLong",
+        0x21 => "busyRepeatRequest",
+        0x22 => "conditionsNotCorrect",
+        0x24 => "requestSequenceError",
+        0x25 => "noResponseFromSubnetComponent",
+        0x26 => "failurePreventsExecutionOfRequestedAction",
+        0x31 => "requestOutOfRange",
+        0x33 => "securityAccessDenied",
+        0x35 => "invalidKey",
+        0x36 => "exceededNumberOfAttempts",
+        0x37 => "requiredTimeDelayNotExpired",
+        0x70 => "uploadDownloadNotAccepted",
+        0x71 => "transferDataSuspended",
+        0x72 => "generalProgrammingFailure",
+        0x73 => "wrongBlockSequenceCounter",
+        0x78 => "requestCorrectlyReceivedResponsePending",
+        0x7E => "subFunctionNotSupportedInActiveSession",
+        0x7F => "serviceNotSupportedInActiveSession",
+        0x81 => "rpmTooHigh",
+        0x82 => "rpmTooLow",
+        0x83 => "engineIsRunning",
+        0x84 => "engineIsNotRunning",
+        0x85 => "engineRunTimeTooLow",
+        0x86 => "temperatureTooHigh",
+        0x87 => "temperatureTooLow",
+        0x88 => "vehicleSpeedTooHigh",
+        0x89 => "vehicleSpeedTooLow",
+        0x8A => "throttlePedalTooHigh",
+        0x8B => "throttlePedalTooLow",
+        0x8C => "transmissionRangeNotInNeutral",
+        0x8D => "transmissionRangeNotInGear",
+        0x8F => "brakeSwitchesNotClosed",
+        0x90 => "shifterLeverNotInPark",
+        0x91 => "torqueConverterClutchLocked",
+        0x92 => "voltageTooHigh",
+        0x93 => "voltageTooLow",
+        _ => "unknown",
+    }
+}
+
+/// How often an ECU answered each service with each negative response code.
+#[derive(Debug, Default)]
+pub struct NegativeResponseLog {
+    counts: BTreeMap<(u8, u8), u64>,
+}
+
+impl NegativeResponseLog {
+    pub fn record(&mut self, response: NegativeResponse) {
+        *self
+            .counts
+            .entry((response.service, response.nrc))
+            .or_default() += 1;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    /// JSON object keyed by service and NRC, e.g.
+    /// `{"22 31":{"service":"22","nrc":"31","name":"requestOutOfRange","count":4}}`.
+    pub fn to_json(&self) -> String {
+        let log: Map<String, Value> = self
+            .counts
+            .iter()
+            .map(|((service, nrc), count)| {
+                (
+                    format!("{:02X} {:02X}", service, nrc),
+                    json!({
+                        "service": format!("{:02X}", service),
+                        "nrc": format!("{:02X}", nrc),
+                        "name": nrc_name(*nrc),
+                        "count": count,
+                    }),
+                )
+            })
+            .collect();
+        Value::Object(log).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payloads_are_classified_by_service_byte() {
+        assert_eq!(
+            classify(&[0x41, 0x0C, 0x1A, 0xF8]),
+            ResponseKind::Positive {
+                service: 0x01,
+                data: &[0x0C, 0x1A, 0xF8],
+            }
+        );
+        assert_eq!(
+            classify(&[0x7F, 0x22, 0x31]),
+            ResponseKind::Negative(NegativeResponse {
+                service: 0x22,
+                nrc: 0x31,
+            })
+        );
+        // A request from another tester, a truncated negative response and nothing at all
+        assert_eq!(classify(&[0x01, 0x0C]), ResponseKind::Unrelated);
+        assert_eq!(classify(&[0x7F, 0x01]), ResponseKind::Unrelated);
+        assert_eq!(classify(&[]), ResponseKind::Unrelated);
+    }
+
+    #[test]
+    fn log_counts_each_service_and_code() {
+        let mut log = NegativeResponseLog::default();
+        let out_of_range = NegativeResponse {
+            service: 0x22,
+            nrc: 0x31,
+        };
+        log.record(out_of_range);
+        log.record(out_of_range);
+
+        assert_eq!(out_of_range.name(), "requestOutOfRange");
+        assert_eq!(
+            log.to_json(),
+            r#"{"22 31":{"count":2,"name":"requestOutOfRange","nrc":"31","service":"22"}}"#
+        );
+    }
+}
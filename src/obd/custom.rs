@@ -0,0 +1,474 @@
+// Custom and manufacturer-specific PID definitions loaded from a TOML file or a
+// Torque-style CSV PID list.
+
+use crate::obd::addressing::{header_id, id_to_hex, physical_response_id};
+use crate::obd::formula::Expression;
+use crate::obd::pids::builtin_signals;
+use crate::obd::request::{SignalRequest, pid_len};
+use crate::vehicle::signals::{DisplayGroup, Formula, SignalDefinition};
+use config::{Config, File, Value};
+use socketcan::embedded_can::Id;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+/// Decimal places used when a definition does not specify any.
+const DEFAULT_PRECISION: usize = 2;
+
+/// A signal from the definitions file and how often to request it.
+#[derive(Debug, Clone)]
+pub struct CustomSignal {
+    pub signal: SignalDefinition,
+    pub rate_hz: f64,
+    /// The identifier the ECU at the signal's header answers on, e.g. 0x77E for requests
+    /// to 0x714; `None` for signals without a header.
+    pub response_header: Option<Id>,
+}
+
+/// Load the definitions in `path`; files ending in `.csv` are read as Torque PID lists,
+/// everything else as TOML with one `[[pids]]` table per signal.
+///
+/// # Arguments
+///
+/// * `path` - The definitions file.
+/// * `default_rate_hz` - Poll rate for definitions that don't set `rate_hz`.
+///
+/// # Returns
+///
+/// Returns the signals, or an error message naming the offending definition.
+pub fn load_custom_signals(path: &str, default_rate_hz: f64) -> Result<Vec<CustomSignal>, String> {
+    check_rate("default", default_rate_hz)?;
+
+    let is_csv = Path::new(path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+
+    let signals = if is_csv {
+        let content = std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+        parse_torque_csv(&content, default_rate_hz)?
+    } else {
+        let settings = Config::builder()
+            .add_source(File::with_name(path))
+            .build()
+            .map_err(|err| format!("{}", err))?;
+        settings
+            .get_array("pids")
+            .map_err(|err| format!("{}: {}", path, err))?
+            .into_iter()
+            .enumerate()
+            .map(|(index, definition)| parse_toml_definition(index, definition, default_rate_hz))
+            .collect::<Result<Vec<_>, String>>()?
+    };
+
+    check_collisions(&signals, &builtin_signals())?;
+
+    Ok(signals)
+}
+
+/// Reject custom signals that share a key with another signal, or a service and PID with
+/// a builtin one; the builtin decoding of a PID would be mixed up with the custom one.
+fn check_collisions(signals: &[CustomSignal], builtins: &[SignalDefinition]) -> Result<(), String> {
+    let mut keys: BTreeSet<&str> = builtins.iter().map(|signal| signal.key.as_str()).collect();
+    for custom in signals {
+        let signal = &custom.signal;
+        if !keys.insert(&signal.key) {
+            return Err(format!("Duplicate custom PID key \"{}\"", signal.key));
+        }
+        if let Some(builtin) = builtins.iter().find(|builtin| {
+            builtin.request.service == signal.request.service
+                && builtin.request.pid == signal.request.pid
+        }) {
+            return Err(format!(
+                "Custom PID \"{}\" uses service {:02X} PID {:02X}, which is decoded as \"{}\" already",
+                signal.name, signal.request.service, signal.request.pid, builtin.name
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// The response IDs of custom headers and the header each of them answers to, which is
+/// also where flow control frames for its multi-frame responses go.
+pub fn response_headers(signals: &[CustomSignal]) -> BTreeMap<Id, Id> {
+    signals
+        .iter()
+        .filter_map(|custom| Some((custom.response_header?, custom.signal.request.header?)))
+        .collect()
+}
+
+/// The poll rate of every distinct request, so signals sharing a request poll it once at
+/// the fastest rate any of them asks for.
+pub fn poll_rates(signals: &[CustomSignal]) -> BTreeMap<SignalRequest, f64> {
+    let mut rates = BTreeMap::new();
+    for custom in signals {
+        let rate = rates.entry(custom.signal.request).or_insert(custom.rate_hz);
+        *rate = custom.rate_hz.max(*rate);
+    }
+    rates
+}
+
+/// Parses one `[[pids]]` table, e.g.
+/// `{ name = "Boost", topic = "BST", header = 0x7E0, service = 0x22, pid = 0x1234, formula = "(A*256+B)/100" }`.
+///
+/// `response_header` defaults to 8 above an 11-bit header and to `18DAF1xx` for `18DAxxF1`.
+fn parse_toml_definition(
+    index: usize,
+    definition: Value,
+    default_rate_hz: f64,
+) -> Result<CustomSignal, String> {
+    let mut table = definition
+        .into_table()
+        .map_err(|err| format!("Invalid custom PID {}: {}", index, err))?;
+
+    let mut string = |field: &str| -> Result<Option<String>, String> {
+        table
+            .remove(field)
+            .map(|value| value.into_string())
+            .transpose()
+            .map_err(|err| format!("Invalid {} in custom PID {}: {}", field, index, err))
+    };
+    let name = string("name")?.ok_or_else(|| format!("Custom PID {} has no name", index))?;
+    let key = string("key")?.unwrap_or_else(|| key_from(&name));
+    let topic = string("topic")?.unwrap_or_else(|| key.clone());
+    let unit = string("unit")?.unwrap_or_default();
+    let formula =
+        string("formula")?.ok_or_else(|| format!("Custom PID \"{}\" has no formula", name))?;
+
+    let invalid = |field: &str| format!("Invalid {} in custom PID \"{}\"", field, name);
+    let mut int = |field: &str| -> Result<Option<i64>, String> {
+        table
+            .remove(field)
+            .map(|value| value.into_int())
+            .transpose()
+            .map_err(|_| invalid(field))
+    };
+    let service = int("service")?.unwrap_or(0x01);
+    let service = u8::try_from(service).map_err(|_| invalid("service"))?;
+    let pid = int("pid")?.ok_or_else(|| format!("Custom PID \"{}\" has no pid", name))?;
+    let mut header = |field: &str| -> Result<Option<Id>, String> {
+        int(field)?
+            .map(|header| u32::try_from(header).ok().and_then(header_id))
+            .map(|header| header.ok_or_else(|| invalid(field)))
+            .transpose()
+    };
+    let request_header = header("header")?;
+    let response_header = response_header(&name, request_header, header("response_header")?)?;
+    let precision = int("precision")?
+        .map(|precision| usize::try_from(precision).map_err(|_| invalid("precision")))
+        .transpose()?
+        .unwrap_or(DEFAULT_PRECISION);
+    let rate_hz = table
+        .remove("rate_hz")
+        .map(|value| value.into_float())
+        .transpose()
+        .map_err(|_| invalid("rate_hz"))?
+        .unwrap_or(default_rate_hz);
+    let rate_hz = check_rate(&name, rate_hz)?;
+
+    Ok(CustomSignal {
+        signal: SignalDefinition {
+            request: signal_request(request_header, service, pid).ok_or_else(|| invalid("pid"))?,
+            formula: parse_formula(&name, &formula)?,
+            key,
+            topic,
+            unit,
+            precision,
+            group: DisplayGroup::Custom,
+            name,
+        },
+        rate_hz,
+        response_header,
+    })
+}
+
+/// The response header of a definition: the configured one, or the physical response ID
+/// of its header, e.g. 0x7E8 for 0x7E0.
+fn response_header(
+    name: &str,
+    header: Option<Id>,
+    response_header: Option<Id>,
+) -> Result<Option<Id>, String> {
+    match (header, response_header) {
+        (None, None) => Ok(None),
+        (None, Some(_)) => Err(format!(
+            "Custom PID \"{}\" has a response_header but no header",
+            name
+        )),
+        (Some(_), Some(response_header)) => Ok(Some(response_header)),
+        (Some(header), None) => physical_response_id(header).map(Some).ok_or_else(|| {
+            format!(
+                "Custom PID \"{}\" needs a response_header for header {}",
+                name,
+                id_to_hex(header)
+            )
+        }),
+    }
+}
+
+/// Parses a Torque PID list: `Name,ShortName,ModeAndPID,Equation,Min Value,Max Value,Units,Header`.
+///
+/// `ModeAndPID` is hex, e.g. `0x221234` for service 0x22 and DID 0x1234, and the optional
+/// header is the hex request ID, e.g. `7E0`.
+fn parse_torque_csv(content: &str, rate_hz: f64) -> Result<Vec<CustomSignal>, String> {
+    let mut signals = Vec::new();
+
+    for (line_number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields = split_csv_line(line);
+        // The header row of Torque exports
+        if fields
+            .first()
+            .is_some_and(|name| name.eq_ignore_ascii_case("name"))
+        {
+            continue;
+        }
+
+        let invalid = |field: &str| format!("Invalid {} on line {}", field, line_number + 1);
+        let field = |index: usize| fields.get(index).map(|f| f.trim()).unwrap_or_default();
+
+        let name = field(0).to_string();
+        let short_name = match field(1) {
+            "" => key_from(&name),
+            short_name => short_name.to_string(),
+        };
+        let mode_and_pid = field(2);
+        let mode_and_pid = mode_and_pid
+            .strip_prefix("0x")
+            .or_else(|| mode_and_pid.strip_prefix("0X"))
+            .unwrap_or(mode_and_pid);
+        if name.is_empty() || mode_and_pid.len() < 4 || !mode_and_pid.is_ascii() {
+            return Err(invalid("name or ModeAndPID"));
+        }
+        let service = u8::from_str_radix(&mode_and_pid[..2], 16).map_err(|_| invalid("mode"))?;
+        let pid = i64::from_str_radix(&mode_and_pid[2..], 16).map_err(|_| invalid("PID"))?;
+        let header = match field(7) {
+            "" => None,
+            header => Some(
+                u32::from_str_radix(header, 16)
+                    .ok()
+                    .and_then(header_id)
+                    .ok_or_else(|| invalid("header"))?,
+            ),
+        };
+
+        let response_header = response_header(&name, header, None)?;
+        signals.push(CustomSignal {
+            signal: SignalDefinition {
+                request: signal_request(header, service, pid).ok_or_else(|| invalid("PID"))?,
+                formula: parse_formula(&name, field(3))?,
+                key: key_from(&short_name),
+                topic: short_name,
+                unit: field(6).to_string(),
+                precision: DEFAULT_PRECISION,
+                group: DisplayGroup::Custom,
+                name,
+            },
+            rate_hz,
+            response_header,
+        });
+    }
+
+    Ok(signals)
+}
+
+/// Split a CSV line on commas outside double quotes, removing the quotes.
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+
+    for c in line.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            _ => fields.last_mut().expect("fields is never empty").push(c),
+        }
+    }
+
+    fields
+}
+
+/// Build the request, checking that the PID fits the service (one byte, two for UDS DIDs).
+fn signal_request(header: Option<Id>, service: u8, pid: i64) -> Option<SignalRequest> {
+    let pid = u16::try_from(pid).ok()?;
+    (pid_len(service) == 2 || pid <= 0xFF).then_some(SignalRequest {
+        header,
+        service,
+        pid,
+    })
+}
+
+/// A signal key derived from a name, e.g. `Boost Pressure` becomes `boost_pressure`.
+fn key_from(name: &str) -> String {
+    name.trim()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn parse_formula(name: &str, formula: &str) -> Result<Formula, String> {
+    Expression::parse(formula)
+        .map(Formula::Expression)
+        .map_err(|err| format!("Invalid formula of custom PID \"{}\": {}", name, err))
+}
+
+fn check_rate(name: &str, rate_hz: f64) -> Result<f64, String> {
+    if rate_hz > 0.0 && rate_hz.is_finite() {
+        Ok(rate_hz)
+    } else {
+        Err(format!(
+            "rate_hz of custom PID \"{}\" must be greater than zero",
+            name
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use socketcan::StandardId;
+
+    const TORQUE_CSV: &str = r#"Name,ShortName,ModeAndPID,Equation,Min Value,Max Value,Units,Header
+"Boost Pressure, Manifold",Boost,0x221234,"(A*256+B)/100",0,300,kPa,7E0
+Oil Temp,,22F40C,A-40,-40,200,°C,
+# Auxiliary inputs
+Aux Input,AUX,0x0165,{A:0},0,1,,
+"#;
+
+    #[test]
+    fn torque_csv_lines_are_imported() {
+        let signals = parse_torque_csv(TORQUE_CSV, 2.0).unwrap();
+        assert_eq!(signals.len(), 3);
+
+        let boost = &signals[0].signal;
+        assert_eq!(boost.name, "Boost Pressure, Manifold");
+        assert_eq!(
+            (boost.key.as_str(), boost.topic.as_str()),
+            ("boost", "Boost")
+        );
+        assert_eq!(boost.unit, "kPa");
+        assert_eq!(
+            boost.request,
+            SignalRequest {
+                header: Some(Id::Standard(StandardId::new(0x7E0).unwrap())),
+                service: 0x22,
+                pid: 0x1234,
+            }
+        );
+        assert_eq!(boost.formula.evaluate(&[0x27, 0x10]), Some(100.0));
+        assert_eq!(signals[0].rate_hz, 2.0);
+
+        let oil = &signals[1].signal;
+        assert_eq!(
+            (oil.key.as_str(), oil.topic.as_str()),
+            ("oil_temp", "oil_temp")
+        );
+        assert_eq!(oil.request.header, None);
+        assert_eq!(oil.request.pid, 0xF40C);
+        assert_eq!(oil.formula.evaluate(&[0x7B]), Some(83.0));
+
+        let aux = &signals[2].signal;
+        assert_eq!(aux.request, SignalRequest::mode01(0x65));
+        assert_eq!(aux.unit, "");
+        assert_eq!(aux.formula.evaluate(&[0x01]), Some(1.0));
+    }
+
+    #[test]
+    fn quoted_fields_keep_their_commas() {
+        assert_eq!(
+            split_csv_line(r#""a, b",c,"(A+B)/2",,"#),
+            vec!["a, b", "c", "(A+B)/2", "", ""]
+        );
+    }
+
+    #[test]
+    fn invalid_torque_lines_name_the_line() {
+        for (csv, error) in [
+            (
+                "Boost,BST,22,A,0,1,kPa,",
+                "Invalid name or ModeAndPID on line 1",
+            ),
+            ("Boost,BST,0x01123,A,0,1,kPa,", "Invalid PID on line 1"),
+            (
+                "Name\nBoost,BST,0x221234,A,0,1,kPa,XYZ",
+                "Invalid header on line 2",
+            ),
+        ] {
+            assert_eq!(parse_torque_csv(csv, 1.0).err().as_deref(), Some(error));
+        }
+        assert!(
+            parse_torque_csv("Boost,BST,0x221234,A+,0,1,kPa,", 1.0)
+                .unwrap_err()
+                .starts_with("Invalid formula of custom PID \"Boost\"")
+        );
+    }
+
+    #[test]
+    fn builtin_keys_and_pids_cannot_be_redefined() {
+        let builtins = builtin_signals();
+        let custom = |csv: &str| parse_torque_csv(csv, 1.0).unwrap();
+
+        assert!(check_collisions(&custom("Knock,KNK,0x0165,A,0,1,,"), &builtins).is_ok());
+        assert!(
+            check_collisions(&custom("Timing,TMG,0x010E,Signed(A)/2,0,1,,"), &builtins)
+                .unwrap_err()
+                .starts_with("Custom PID \"Timing\" uses service 01 PID 0E")
+        );
+        let builtin_key = &builtins[0].key;
+        assert_eq!(
+            check_collisions(
+                &custom(&format!("Other,{},0x221234,A,0,1,,", builtin_key)),
+                &builtins
+            ),
+            Err(format!("Duplicate custom PID key \"{}\"", builtin_key))
+        );
+        assert!(
+            check_collisions(
+                &custom("A,X,0x221234,A,0,1,,\nB,X,0x221235,A,0,1,,"),
+                &builtins
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn response_headers_map_to_their_request_header() {
+        let definition = |response_header: Option<i64>, header: Option<i64>| {
+            let mut table = config::Map::new();
+            table.insert("name".to_string(), Value::from("Outside Temp"));
+            table.insert("service".to_string(), Value::from(0x22));
+            table.insert("pid".to_string(), Value::from(0x2203));
+            table.insert("formula".to_string(), Value::from("A/2-40"));
+            if let Some(header) = header {
+                table.insert("header".to_string(), Value::from(header));
+            }
+            if let Some(response_header) = response_header {
+                table.insert("response_header".to_string(), Value::from(response_header));
+            }
+            parse_toml_definition(0, Value::from(table), 1.0)
+        };
+        let id = |raw: u16| Id::Standard(StandardId::new(raw).unwrap());
+
+        let custom = definition(Some(0x77E), Some(0x714)).unwrap();
+        assert_eq!(custom.response_header, Some(id(0x77E)));
+        assert_eq!(
+            response_headers(&[custom]),
+            BTreeMap::from([(id(0x77E), id(0x714))])
+        );
+        assert!(definition(Some(0x77E), None).is_err());
+        assert!(definition(Some(-1), Some(0x714)).is_err());
+        // Without a response_header the ECU answers 8 above its header
+        let custom = definition(None, Some(0x7E0)).unwrap();
+        assert_eq!(custom.response_header, Some(id(0x7E8)));
+        assert_eq!(definition(None, None).unwrap().response_header, None);
+        assert!(definition(None, Some(0x7FA)).is_err());
+    }
+}
@@ -0,0 +1,135 @@
+// Supported-PID discovery through the mode 01 bitmaps at PIDs 0x00, 0x20, 0x40, ... 0xA0.
+
+use crate::isotp::{IsoTpChannel, IsoTpError};
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+/// How long to wait for the ECU to answer each bitmap request.
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// The PIDs whose responses describe the next 32 PIDs.
+const BITMAP_PIDS: [u8; 6] = [0x00, 0x20, 0x40, 0x60, 0x80, 0xA0];
+
+/// Decode a supported-PID bitmap returned for `base`.
+///
+/// Bit 31 of the bitmap stands for PID `base + 1`, bit 0 for PID `base + 32`.
+pub fn decode_bitmap(base: u8, bitmap: [u8; 4]) -> impl Iterator<Item = u8> {
+    let bits = u32::from_be_bytes(bitmap);
+    (0..32u8)
+        .filter(move |bit| bits & (1 << (31 - bit)) != 0)
+        .map(move |bit| base.wrapping_add(bit + 1))
+}
+
+/// Ask the ECU behind `channel` which mode 01 PIDs it supports.
+///
+/// Bitmaps are requested in order and the walk stops as soon as a bitmap reports that
+/// the next range is not supported.
+///
+/// # Arguments
+///
+/// * `channel` - A physical ISO-TP channel to the ECU.
+///
+/// # Returns
+///
+/// Returns the supported PIDs, or an error if the ECU did not answer the first bitmap request.
+pub async fn discover_supported_pids(
+    channel: &IsoTpChannel<'_>,
+) -> Result<BTreeSet<u8>, IsoTpError> {
+    let mut supported = BTreeSet::new();
+
+    for base in BITMAP_PIDS {
+        let response = match channel.request(&[0x01, base], RESPONSE_TIMEOUT).await {
+            Ok(response) => response,
+            // Later ranges are optional; only a silent first request is an error.
+            Err(e) if base == 0x00 => return Err(e),
+            Err(_) => break,
+        };
+
+        match add_bitmap(&mut supported, base, &response) {
+            Some(true) => {}
+            None if base == 0x00 => return Err(IsoTpError::MalformedFrame),
+            Some(false) | None => break,
+        }
+    }
+
+    Ok(supported)
+}
+
+/// Add the PIDs of the bitmap response `41 <base> A B C D` to `supported`.
+///
+/// # Returns
+///
+/// Returns whether the bitmap of the next range is supported, or `None` if `response` is
+/// not the bitmap for `base`.
+fn add_bitmap(supported: &mut BTreeSet<u8>, base: u8, response: &[u8]) -> Option<bool> {
+    match response {
+        [0x41, pid, a, b, c, d, ..] if *pid == base => {
+            supported.extend(decode_bitmap(base, [*a, *b, *c, *d]));
+            Some(supported.contains(&base.wrapping_add(0x20)))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bitmap_bits_count_from_the_most_significant() {
+        for (base, bitmap, pids) in [
+            (0x00, [0x80, 0x00, 0x00, 0x00], vec![0x01]),
+            (0x00, [0x00, 0x00, 0x00, 0x01], vec![0x20]),
+            (0x00, [0x40, 0x00, 0x80, 0x00], vec![0x02, 0x11]),
+            (0x20, [0x80, 0x00, 0x00, 0x01], vec![0x21, 0x40]),
+            (0xA0, [0x00, 0x00, 0x00, 0x01], vec![0xC0]),
+            (0x40, [0x00, 0x00, 0x00, 0x00], vec![]),
+        ] {
+            assert_eq!(decode_bitmap(base, bitmap).collect::<Vec<_>>(), pids);
+        }
+        // A typical gasoline engine: 01, 03-07, 0C-11, 13, 15, 1C, 1F, 20
+        let pids: Vec<u8> = decode_bitmap(0x00, [0xBE, 0x1F, 0xA8, 0x13]).collect();
+        assert_eq!(
+            pids,
+            vec![
+                0x01, 0x03, 0x04, 0x05, 0x06, 0x07, 0x0C, 0x0D, 0x0E, 0x0F, 0x10, 0x11, 0x13, 0x15,
+                0x1C, 0x1F, 0x20,
+            ]
+        );
+    }
+
+    #[test]
+    fn bitmaps_chain_while_the_next_range_is_supported() {
+        let mut supported = BTreeSet::new();
+        assert_eq!(
+            add_bitmap(&mut supported, 0x00, &[0x41, 0x00, 0x80, 0x00, 0x00, 0x01]),
+            Some(true)
+        );
+        assert_eq!(
+            add_bitmap(&mut supported, 0x20, &[0x41, 0x20, 0x00, 0x00, 0x00, 0x01]),
+            Some(true)
+        );
+        // PID 0x60 is not supported, so the walk stops after 0x40
+        assert_eq!(
+            add_bitmap(&mut supported, 0x40, &[0x41, 0x40, 0x40, 0x00, 0x00, 0x00]),
+            Some(false)
+        );
+        assert_eq!(
+            supported.into_iter().collect::<Vec<_>>(),
+            vec![0x01, 0x20, 0x40, 0x42]
+        );
+    }
+
+    #[test]
+    fn other_answers_are_not_bitmaps() {
+        let mut supported = BTreeSet::new();
+        for response in [
+            &[0x41, 0x20, 0xFF, 0xFF, 0xFF, 0xFF][..],
+            &[0x41, 0x00, 0xFF, 0xFF],
+            &[0x7F, 0x01, 0x12],
+        ] {
+            assert_eq!(add_bitmap(&mut supported, 0x00, response), None);
+        }
+        assert!(supported.is_empty());
+    }
+}
@@ -0,0 +1,239 @@
+// Diagnostic trouble codes: stored (mode 03), pending (mode 07) and permanent (mode 0A).
+
+use crate::obd::uds::RESPONSE_PENDING;
+
+/// The three DTC lists an emissions ECU can report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DtcKind {
+    Stored,
+    Pending,
+    Permanent,
+}
+
+impl DtcKind {
+    pub const ALL: [DtcKind; 3] = [DtcKind::Stored, DtcKind::Pending, DtcKind::Permanent];
+
+    /// The OBD service used to request this list.
+    pub fn service(self) -> u8 {
+        match self {
+            DtcKind::Stored => 0x03,
+            DtcKind::Pending => 0x07,
+            DtcKind::Permanent => 0x0A,
+        }
+    }
+
+    /// The MQTT topic suffix the list is published under.
+    pub fn topic(self) -> &'static str {
+        match self {
+            DtcKind::Stored => "dtc/stored",
+            DtcKind::Pending => "dtc/pending",
+            DtcKind::Permanent => "dtc/permanent",
+        }
+    }
+
+    fn from_response_service(service: u8) -> Option<Self> {
+        DtcKind::ALL
+            .into_iter()
+            .find(|kind| kind.service() + 0x40 == service)
+    }
+}
+
+/// The most recently reported DTC lists; `None` until the ECU has answered.
+#[derive(Default, Debug)]
+pub struct TroubleCodes {
+    pub stored: Option<Vec<String>>,
+    pub pending: Option<Vec<String>>,
+    pub permanent: Option<Vec<String>>,
+}
+
+impl TroubleCodes {
+    pub fn get(&self, kind: DtcKind) -> Option<&Vec<String>> {
+        match kind {
+            DtcKind::Stored => self.stored.as_ref(),
+            DtcKind::Pending => self.pending.as_ref(),
+            DtcKind::Permanent => self.permanent.as_ref(),
+        }
+    }
+
+    fn set(&mut self, kind: DtcKind, codes: Vec<String>) {
+        match kind {
+            DtcKind::Stored => self.stored = Some(codes),
+            DtcKind::Pending => self.pending = Some(codes),
+            DtcKind::Permanent => self.permanent = Some(codes),
+        }
+    }
+}
+
+/// Decode a two-byte DTC into its SAE J2012 string form, e.g. `[0x01, 0x33]` -> `P0133`.
+pub fn decode_dtc(high: u8, low: u8) -> String {
+    let system = match high >> 6 {
+        0 => 'P',
+        1 => 'C',
+        2 => 'B',
+        _ => 'U',
+    };
+    format!(
+        "{}{}{:01X}{:02X}",
+        system,
+        (high >> 4) & 0x03,
+        high & 0x0F,
+        low
+    )
+}
+
+/// Decode a mode 03/07/0A response payload into `codes`.
+///
+/// `bytes` is the reassembled ISO-TP payload: the response service byte, the number of
+/// DTCs (on ISO 15765-4) and then two bytes per code.
+///
+/// # Returns
+///
+/// Returns the kind of list that was updated, or `None` if the payload is not a DTC response.
+pub fn parse_dtc_response(bytes: &[u8], codes: &mut TroubleCodes) -> Option<DtcKind> {
+    let kind = DtcKind::from_response_service(*bytes.first()?)?;
+    let count = *bytes.get(1)? as usize;
+
+    let list = bytes[2..]
+        .chunks_exact(2)
+        .take(count)
+        // 0x0000 is padding, not a real code.
+        .filter(|pair| pair[0] != 0 || pair[1] != 0)
+        .map(|pair| decode_dtc(pair[0], pair[1]))
+        .collect();
+
+    codes.set(kind, list);
+    Some(kind)
+}
+
+/// The ECU's answer to a mode 04 "clear DTCs" request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClearResult {
+    /// Positive response (0x44): codes and freeze frames were erased.
+    Cleared,
+    /// Negative response (0x7F) carrying the ECU's response code, e.g. 0x22 conditions not correct.
+    Rejected(u8),
+    /// The ECU did not answer in time.
+    NoResponse,
+}
+
+impl ClearResult {
+    /// JSON report published on the clear-DTC result topic.
+    pub fn to_json(self) -> String {
+        match self {
+            ClearResult::Cleared => r#"{"success":true}"#.to_string(),
+            ClearResult::Rejected(nrc) => {
+                format!(r#"{{"success":false,"nrc":"0x{:02X}"}}"#, nrc)
+            }
+            ClearResult::NoResponse => r#"{"success":false,"error":"no response"}"#.to_string(),
+        }
+    }
+}
+
+/// Decode the response to a mode 04 request.
+///
+/// # Returns
+///
+/// Returns `None` if the payload is not a response to mode 04, or if it is a
+/// response-pending answer (NRC 0x78) and the real answer is still to come.
+pub fn parse_clear_response(bytes: &[u8]) -> Option<ClearResult> {
+    match bytes {
+        [0x44, ..] => Some(ClearResult::Cleared),
+        [0x7F, 0x04, RESPONSE_PENDING, ..] => None,
+        [0x7F, 0x04, nrc, ..] => Some(ClearResult::Rejected(*nrc)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn system_letter_comes_from_the_top_bits() {
+        assert_eq!(decode_dtc(0x01, 0x33), "P0133");
+        assert_eq!(decode_dtc(0x41, 0x23), "C0123");
+        assert_eq!(decode_dtc(0x9A, 0xBC), "B1ABC");
+        assert_eq!(decode_dtc(0xC1, 0x00), "U0100");
+        assert_eq!(decode_dtc(0x30, 0x0F), "P300F");
+    }
+
+    #[test]
+    fn every_listed_code_is_decoded() {
+        let mut codes = TroubleCodes::default();
+        assert_eq!(codes.get(DtcKind::Stored), None);
+
+        let kind = parse_dtc_response(
+            &[0x43, 0x03, 0x01, 0x33, 0x41, 0x23, 0xC1, 0x00],
+            &mut codes,
+        );
+        assert_eq!(kind, Some(DtcKind::Stored));
+        assert_eq!(
+            codes.get(DtcKind::Stored),
+            Some(&vec![
+                "P0133".to_string(),
+                "C0123".to_string(),
+                "U0100".to_string()
+            ])
+        );
+
+        // An empty list replaces the previous one
+        parse_dtc_response(&[0x43, 0x00], &mut codes);
+        assert_eq!(codes.get(DtcKind::Stored), Some(&Vec::new()));
+    }
+
+    #[test]
+    fn padding_and_odd_bytes_are_ignored() {
+        let mut codes = TroubleCodes::default();
+
+        // Zero padding up to the end of the frame
+        parse_dtc_response(
+            &[0x47, 0x01, 0x01, 0x71, 0x00, 0x00, 0x00, 0x00],
+            &mut codes,
+        );
+        assert_eq!(
+            codes.get(DtcKind::Pending),
+            Some(&vec!["P0171".to_string()])
+        );
+
+        // A trailing half code and codes beyond the count
+        parse_dtc_response(&[0x4A, 0x01, 0x04, 0x20, 0x03, 0x00, 0x01], &mut codes);
+        assert_eq!(
+            codes.get(DtcKind::Permanent),
+            Some(&vec!["P0420".to_string()])
+        );
+        parse_dtc_response(&[0x4A, 0x02, 0x04, 0x20, 0x03], &mut codes);
+        assert_eq!(
+            codes.get(DtcKind::Permanent),
+            Some(&vec!["P0420".to_string()])
+        );
+    }
+
+    #[test]
+    fn other_payloads_are_not_dtc_responses() {
+        let mut codes = TroubleCodes::default();
+        assert_eq!(
+            parse_dtc_response(&[0x41, 0x0C, 0x1A, 0xF8], &mut codes),
+            None
+        );
+        assert_eq!(parse_dtc_response(&[0x43], &mut codes), None);
+        assert_eq!(parse_dtc_response(&[], &mut codes), None);
+        assert_eq!(codes.get(DtcKind::Stored), None);
+    }
+
+    #[test]
+    fn response_pending_waits_for_the_real_answer() {
+        let answers: Vec<_> = [&[0x7F, 0x04, 0x78][..], &[0x44]]
+            .into_iter()
+            .map(parse_clear_response)
+            .collect();
+        assert_eq!(answers, vec![None, Some(ClearResult::Cleared)]);
+    }
+
+    #[test]
+    fn negative_response_rejects_the_clear() {
+        assert_eq!(
+            parse_clear_response(&[0x7F, 0x04, 0x22]),
+            Some(ClearResult::Rejected(0x22))
+        );
+    }
+}
@@ -0,0 +1,364 @@
+// Arithmetic formulas for custom PIDs, in the notation used by Torque PID lists:
+// `A`..`Z`, `AA`..`AZ` are the data bytes after the echoed PID, e.g. `(A*256+B)/100`.
+
+use std::fmt;
+use thiserror::Error;
+
+/// Errors that can occur while parsing a formula.
+#[derive(Debug, Error, PartialEq)]
+pub enum FormulaError {
+    #[error("Unexpected character '{0}' at position {1}")]
+    UnexpectedChar(char, usize),
+    #[error("Unexpected end of formula")]
+    UnexpectedEnd,
+    #[error("Unexpected \"{0}\"")]
+    UnexpectedToken(String),
+    #[error("Unknown function \"{0}\"")]
+    UnknownFunction(String),
+    #[error("Invalid bit reference \"{0}\"")]
+    InvalidBit(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Operator(char),
+    Open,
+    Close,
+    /// `{A:7}`: bit 7 of byte A.
+    Bit(usize, u8),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "{}", value),
+            Token::Name(name) => write!(f, "{}", name),
+            Token::Operator(op) => write!(f, "{}", op),
+            Token::Open => write!(f, "("),
+            Token::Close => write!(f, ")"),
+            Token::Bit(index, bit) => write!(f, "{{byte {}:{}}}", index, bit),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Number(f64),
+    Byte(usize),
+    /// A byte interpreted as a two's complement `i8`.
+    SignedByte(usize),
+    Bit(usize, u8),
+    Negate(Box<Node>),
+    Binary(char, Box<Node>, Box<Node>),
+}
+
+/// A parsed formula that can be evaluated against response bytes.
+#[derive(Debug, Clone)]
+pub struct Expression {
+    source: String,
+    root: Node,
+    data_len: usize,
+}
+
+impl Expression {
+    /// Parse a formula such as `(A*256+B)/4`, `Signed(A)-40` or `{B:3}`.
+    pub fn parse(source: &str) -> Result<Self, FormulaError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            position: 0,
+        };
+        let root = parser.expression()?;
+        if let Some(token) = parser.peek() {
+            return Err(FormulaError::UnexpectedToken(token.to_string()));
+        }
+
+        Ok(Expression {
+            source: source.to_string(),
+            data_len: data_len(&root),
+            root,
+        })
+    }
+
+    /// The formula as written in the definition.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Number of data bytes the formula reads, e.g. 2 for `(A*256+B)/4`.
+    pub fn data_len(&self) -> usize {
+        self.data_len
+    }
+
+    /// Evaluate the formula against the data bytes that follow the echoed PID.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` if the formula references a byte the response does not contain or
+    /// divides by zero.
+    pub fn evaluate(&self, data: &[u8]) -> Option<f64> {
+        evaluate(&self.root, data)
+    }
+}
+
+fn evaluate(node: &Node, data: &[u8]) -> Option<f64> {
+    match node {
+        Node::Number(value) => Some(*value),
+        Node::Byte(index) => data.get(*index).map(|b| *b as f64),
+        Node::SignedByte(index) => data.get(*index).map(|b| *b as i8 as f64),
+        Node::Bit(index, bit) => data.get(*index).map(|b| ((b >> bit) & 1) as f64),
+        Node::Negate(inner) => Some(-evaluate(inner, data)?),
+        Node::Binary(op, left, right) => {
+            let left = evaluate(left, data)?;
+            let right = evaluate(right, data)?;
+            match op {
+                '+' => Some(left + right),
+                '-' => Some(left - right),
+                '*' => Some(left * right),
+                '/' if right != 0.0 => Some(left / right),
+                _ => None,
+            }
+        }
+    }
+}
+
+fn data_len(node: &Node) -> usize {
+    match node {
+        Node::Number(_) => 0,
+        Node::Byte(index) | Node::SignedByte(index) | Node::Bit(index, _) => index + 1,
+        Node::Negate(inner) => data_len(inner),
+        Node::Binary(_, left, right) => data_len(left).max(data_len(right)),
+    }
+}
+
+/// Byte index of a variable name: `A` is 0, `Z` is 25, `AA` is 26, `AZ` is 51.
+fn byte_index(name: &str) -> Option<usize> {
+    let letter = |c: u8| (c as usize).checked_sub(b'A' as usize).filter(|i| *i < 26);
+    match name.as_bytes() {
+        [c] => letter(*c),
+        [b'A', c] => letter(*c).map(|i| 26 + i),
+        _ => None,
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, FormulaError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            ' ' | '\t' => i += 1,
+            '+' | '-' | '*' | '/' => {
+                tokens.push(Token::Operator(c));
+                i += 1;
+            }
+            '(' => {
+                tokens.push(Token::Open);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::Close);
+                i += 1;
+            }
+            '{' => {
+                let end = chars[i..]
+                    .iter()
+                    .position(|c| *c == '}')
+                    .ok_or(FormulaError::UnexpectedEnd)?;
+                let inner: String = chars[i + 1..i + end].iter().collect();
+                let invalid = || FormulaError::InvalidBit(inner.clone());
+                let (name, bit) = inner.split_once(':').ok_or_else(invalid)?;
+                let index = byte_index(name.trim()).ok_or_else(invalid)?;
+                let bit = bit
+                    .trim()
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|bit| *bit < 8)
+                    .ok_or_else(invalid)?;
+                tokens.push(Token::Bit(index, bit));
+                i += end + 1;
+            }
+            _ if c.is_ascii_digit() || c == '.' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '.') {
+                    i += 1;
+                }
+                let text: String = chars[start..i].iter().collect();
+                let value = match text.strip_prefix("0x") {
+                    Some(hex) => u64::from_str_radix(hex, 16).map(|v| v as f64).ok(),
+                    None => text.parse::<f64>().ok(),
+                };
+                tokens.push(Token::Number(
+                    value.ok_or(FormulaError::UnexpectedToken(text))?,
+                ));
+            }
+            _ if c.is_ascii_alphabetic() => {
+                let start = i;
+                while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                    i += 1;
+                }
+                tokens.push(Token::Name(chars[start..i].iter().collect()));
+            }
+            _ => return Err(FormulaError::UnexpectedChar(c, i)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Recursive descent parser: expression = term (('+' | '-') term)*,
+/// term = factor (('*' | '/') factor)*, factor = '-' factor | number | byte | call | '(' expression ')'.
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, FormulaError> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or(FormulaError::UnexpectedEnd)?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), FormulaError> {
+        match self.next()? {
+            token if token == expected => Ok(()),
+            token => Err(FormulaError::UnexpectedToken(token.to_string())),
+        }
+    }
+
+    fn expression(&mut self) -> Result<Node, FormulaError> {
+        let mut node = self.term()?;
+        while let Some(Token::Operator(op @ ('+' | '-'))) = self.peek().cloned() {
+            self.position += 1;
+            node = Node::Binary(op, Box::new(node), Box::new(self.term()?));
+        }
+        Ok(node)
+    }
+
+    fn term(&mut self) -> Result<Node, FormulaError> {
+        let mut node = self.factor()?;
+        while let Some(Token::Operator(op @ ('*' | '/'))) = self.peek().cloned() {
+            self.position += 1;
+            node = Node::Binary(op, Box::new(node), Box::new(self.factor()?));
+        }
+        Ok(node)
+    }
+
+    fn factor(&mut self) -> Result<Node, FormulaError> {
+        match self.next()? {
+            Token::Operator('-') => Ok(Node::Negate(Box::new(self.factor()?))),
+            Token::Number(value) => Ok(Node::Number(value)),
+            Token::Bit(index, bit) => Ok(Node::Bit(index, bit)),
+            Token::Open => {
+                let node = self.expression()?;
+                self.expect(Token::Close)?;
+                Ok(node)
+            }
+            Token::Name(name) if self.peek() == Some(&Token::Open) => {
+                self.position += 1;
+                let node = match name.to_ascii_lowercase().as_str() {
+                    "signed" => {
+                        let token = self.next()?;
+                        match &token {
+                            Token::Name(byte) => byte_index(byte).map(Node::SignedByte),
+                            _ => None,
+                        }
+                        .ok_or(FormulaError::UnexpectedToken(token.to_string()))?
+                    }
+                    _ => return Err(FormulaError::UnknownFunction(name)),
+                };
+                self.expect(Token::Close)?;
+                Ok(node)
+            }
+            Token::Name(name) => byte_index(&name)
+                .map(Node::Byte)
+                .ok_or(FormulaError::UnexpectedToken(name)),
+            token => Err(FormulaError::UnexpectedToken(token.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(source: &str, data: &[u8]) -> Option<f64> {
+        Expression::parse(source).unwrap().evaluate(data)
+    }
+
+    #[test]
+    fn precedence_and_parentheses() {
+        assert_eq!(eval("A*256+B", &[1, 2]), Some(258.0));
+        assert_eq!(eval("A+B*2", &[1, 2]), Some(5.0));
+        assert_eq!(eval("(A+B)*2", &[1, 2]), Some(6.0));
+        assert_eq!(eval("(A*256+B)/4", &[0x1A, 0xF8]), Some(1726.0));
+        assert_eq!(eval("100-A-B", &[10, 20]), Some(70.0));
+        assert_eq!(eval("A/4/2", &[16]), Some(2.0));
+        assert_eq!(eval("-A+-(B-3)", &[1, 5]), Some(-3.0));
+        assert_eq!(eval("0x10 * 1.5", &[]), Some(24.0));
+    }
+
+    #[test]
+    fn signed_bytes_and_bits() {
+        assert_eq!(eval("Signed(A)/2", &[0xF0]), Some(-8.0));
+        assert_eq!(eval("SIGNED(B)", &[0x00, 0x7F]), Some(127.0));
+        assert_eq!(eval("{A:7}", &[0x80]), Some(1.0));
+        assert_eq!(eval("{B:0}*10", &[0x00, 0xFE]), Some(0.0));
+    }
+
+    #[test]
+    fn byte_references_and_data_len() {
+        let data: Vec<u8> = (0..52).collect();
+        assert_eq!(eval("Z", &data), Some(25.0));
+        assert_eq!(eval("AA", &data), Some(26.0));
+        assert_eq!(eval("AZ", &data), Some(51.0));
+
+        let data_len = |source: &str| Expression::parse(source).unwrap().data_len();
+        assert_eq!(data_len("42"), 0);
+        assert_eq!(data_len("(A*256+B)/100"), 2);
+        assert_eq!(data_len("Signed(D)"), 4);
+        assert_eq!(data_len("{C:1}+A"), 3);
+        assert_eq!(data_len("AB-A"), 28);
+        // Referenced bytes the response does not contain
+        assert_eq!(eval("A+C", &[1, 2]), None);
+    }
+
+    #[test]
+    fn division_by_zero_has_no_value() {
+        assert_eq!(eval("A/B", &[10, 0]), None);
+        assert_eq!(eval("A/(B-B)", &[10, 3]), None);
+    }
+
+    #[test]
+    fn malformed_formulas_are_errors() {
+        for (source, error) in [
+            ("", FormulaError::UnexpectedEnd),
+            ("A+", FormulaError::UnexpectedEnd),
+            ("(A*2", FormulaError::UnexpectedEnd),
+            ("A)", FormulaError::UnexpectedToken(")".to_string())),
+            ("A % 2", FormulaError::UnexpectedChar('%', 2)),
+            ("Foo(A)", FormulaError::UnknownFunction("Foo".to_string())),
+            ("Signed(3)", FormulaError::UnexpectedToken("3".to_string())),
+            ("BA", FormulaError::UnexpectedToken("BA".to_string())),
+            ("{A:8}", FormulaError::InvalidBit("A:8".to_string())),
+            ("{A:1", FormulaError::UnexpectedEnd),
+            ("1.2.3", FormulaError::UnexpectedToken("1.2.3".to_string())),
+            ("2A", FormulaError::UnexpectedToken("2A".to_string())),
+        ] {
+            assert_eq!(Expression::parse(source).err(), Some(error), "{}", source);
+        }
+    }
+}
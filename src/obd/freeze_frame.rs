@@ -0,0 +1,203 @@
+// Freeze frame data (mode 02): the snapshot the ECU stores when a DTC sets.
+
+use crate::obd::dtc::decode_dtc;
+use crate::obd::response::{DecodeError, parse_obd_response};
+use crate::vehicle::data::VehicleData;
+use crate::vehicle::signals::SignalRegistry;
+use serde_json::{Map, Value};
+
+/// Mode 02 PID that returns the DTC which caused the freeze frame.
+const PID_FREEZE_FRAME_DTC: u8 = 0x02;
+
+/// Mode 01 PIDs whose freeze frame values are requested.
+const FREEZE_FRAME_PIDS: [u8; 14] = [
+    0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F, 0x10, 0x11,
+];
+
+/// The PIDs to request for freeze frame 0: the causing DTC first, then the values.
+pub fn freeze_frame_pids() -> impl Iterator<Item = u8> {
+    std::iter::once(PID_FREEZE_FRAME_DTC).chain(FREEZE_FRAME_PIDS)
+}
+
+/// The freeze frame reported by one ECU.
+#[derive(Default, Debug)]
+pub struct FreezeFrame {
+    /// Whether the ECU has answered the freeze frame DTC request yet.
+    pub reported: bool,
+    /// The DTC that caused the freeze frame, `None` if none is stored.
+    pub dtc: Option<String>,
+    data: VehicleData,
+}
+
+impl FreezeFrame {
+    /// JSON report tying the snapshot to its DTC, e.g.
+    /// `{"dtc":"P0301","values":{"coolant_temp":88,"engine_rpm":2450.5}}`.
+    ///
+    /// `dtc` is `null` when no freeze frame is stored.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` while the ECU has not answered the freeze frame DTC request.
+    pub fn to_json(&self, registry: &SignalRegistry) -> Option<String> {
+        if !self.reported {
+            return None;
+        }
+
+        let values: Map<String, Value> = registry
+            .iter()
+            .filter_map(|signal| {
+                let value = self.data.get(&signal.key)?;
+                // Round through the signal's precision so 0.3 doesn't become 0.30000000000000004
+                let value = signal.format(value).parse::<f64>().ok()?;
+                Some((signal.key.clone(), Value::from(value)))
+            })
+            .collect();
+
+        let mut report = Map::new();
+        report.insert("dtc".to_string(), Value::from(self.dtc.clone()));
+        report.insert("values".to_string(), Value::Object(values));
+        Some(Value::Object(report).to_string())
+    }
+}
+
+/// Decode a mode 02 response payload into `freeze_frame`.
+///
+/// `bytes` is the reassembled ISO-TP payload: `0x42`, the PID, the frame number and the
+/// PID's data bytes, which are scaled exactly like their mode 01 counterparts.
+///
+/// # Returns
+///
+/// Returns an error if the payload is not a mode 02 response or a value could not be decoded.
+pub fn parse_freeze_frame_response(
+    bytes: &[u8],
+    registry: &SignalRegistry,
+    freeze_frame: &mut FreezeFrame,
+) -> Result<(), DecodeError> {
+    let [0x42, pid, _frame, data @ ..] = bytes else {
+        return Err(match bytes.first() {
+            Some(service) => DecodeError::UnexpectedService(*service),
+            None => DecodeError::Empty,
+        });
+    };
+
+    if *pid == PID_FREEZE_FRAME_DTC {
+        if let [high, low, ..] = data {
+            // 0x0000 means no freeze frame is stored.
+            let dtc = (*high != 0 || *low != 0).then(|| decode_dtc(*high, *low));
+            if !freeze_frame.reported || dtc != freeze_frame.dtc {
+                *freeze_frame = FreezeFrame {
+                    reported: true,
+                    dtc,
+                    ..FreezeFrame::default()
+                };
+            }
+        }
+        return Ok(());
+    }
+
+    let mut mode01 = vec![0x41, *pid];
+    mode01.extend_from_slice(data);
+    parse_obd_response(&mode01, registry, &mut freeze_frame.data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obd::pids::builtin_signals;
+    use serde_json::json;
+
+    fn report(freeze_frame: &FreezeFrame, registry: &SignalRegistry) -> Option<Value> {
+        let json = freeze_frame.to_json(registry)?;
+        Some(serde_json::from_str(&json).unwrap())
+    }
+
+    #[test]
+    fn values_skip_the_frame_number() {
+        let registry = SignalRegistry::new(builtin_signals());
+        let mut freeze_frame = FreezeFrame::default();
+
+        let result = parse_freeze_frame_response(
+            &[0x42, 0x0C, 0x00, 0x1A, 0xF8],
+            &registry,
+            &mut freeze_frame,
+        );
+        assert_eq!(result, Ok(()));
+        assert_eq!(freeze_frame.data.get("engine_rpm"), Some(1726.0));
+        // Nothing is reported until the DTC request is answered
+        assert_eq!(report(&freeze_frame, &registry), None);
+    }
+
+    #[test]
+    fn dtc_pid_ties_the_values_to_their_code() {
+        let registry = SignalRegistry::new(builtin_signals());
+        let mut freeze_frame = FreezeFrame::default();
+
+        for response in [
+            &[0x42, 0x02, 0x00, 0x03, 0x01][..],
+            &[0x42, 0x05, 0x00, 0x7B],
+            &[0x42, 0x0C, 0x00, 0x1A, 0xF8],
+        ] {
+            parse_freeze_frame_response(response, &registry, &mut freeze_frame).unwrap();
+        }
+        assert_eq!(freeze_frame.dtc.as_deref(), Some("P0301"));
+        assert_eq!(
+            report(&freeze_frame, &registry),
+            Some(json!({"dtc": "P0301", "values": {"coolant_temp": 83.0, "engine_rpm": 1726.0}}))
+        );
+
+        // The same DTC again keeps the values
+        parse_freeze_frame_response(
+            &[0x42, 0x02, 0x00, 0x03, 0x01],
+            &registry,
+            &mut freeze_frame,
+        )
+        .unwrap();
+        assert_eq!(freeze_frame.data.get("coolant_temp"), Some(83.0));
+
+        // A new DTC starts a new snapshot
+        parse_freeze_frame_response(
+            &[0x42, 0x02, 0x00, 0x01, 0x71],
+            &registry,
+            &mut freeze_frame,
+        )
+        .unwrap();
+        assert_eq!(
+            report(&freeze_frame, &registry),
+            Some(json!({"dtc": "P0171", "values": {}}))
+        );
+    }
+
+    #[test]
+    fn zero_dtc_means_no_freeze_frame() {
+        let registry = SignalRegistry::new(builtin_signals());
+        let mut freeze_frame = FreezeFrame::default();
+
+        parse_freeze_frame_response(
+            &[0x42, 0x02, 0x00, 0x00, 0x00],
+            &registry,
+            &mut freeze_frame,
+        )
+        .unwrap();
+        assert!(freeze_frame.reported);
+        assert_eq!(
+            report(&freeze_frame, &registry),
+            Some(json!({"dtc": null, "values": {}}))
+        );
+    }
+
+    #[test]
+    fn other_services_are_rejected() {
+        let registry = SignalRegistry::new(builtin_signals());
+        let mut freeze_frame = FreezeFrame::default();
+
+        assert_eq!(
+            parse_freeze_frame_response(&[], &registry, &mut freeze_frame),
+            Err(DecodeError::Empty)
+        );
+        assert_eq!(
+            parse_freeze_frame_response(&[0x41, 0x0C, 0x1A, 0xF8], &registry, &mut freeze_frame),
+            Err(DecodeError::UnexpectedService(0x41))
+        );
+        assert!(!freeze_frame.reported);
+    }
+}
@@ -0,0 +1,167 @@
+// Vehicle identification through mode 09: VIN, calibration IDs, CVNs and ECU name.
+
+use crate::isotp::IsoTpChannel;
+use std::time::Duration;
+
+/// How long to wait for each mode 09 answer; VIN and CALID responses span several frames.
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(500);
+
+const INFO_VIN: u8 = 0x02;
+const INFO_CALIBRATION_ID: u8 = 0x04;
+const INFO_CVN: u8 = 0x06;
+const INFO_ECU_NAME: u8 = 0x0A;
+
+/// Identity of the vehicle and its engine ECU.
+#[derive(Default, Debug, Clone)]
+pub struct VehicleInfo {
+    pub vin: Option<String>,
+    pub calibration_ids: Vec<String>,
+    pub cvns: Vec<String>,
+    pub ecu_name: Option<String>,
+}
+
+/// Read the vehicle identity once through `channel`.
+///
+/// Every InfoType is optional: one the ECU does not support is left empty and the others
+/// are still read.
+///
+/// # Arguments
+///
+/// * `channel` - A physical ISO-TP channel to the engine ECU.
+///
+/// # Returns
+///
+/// Returns whatever identification the ECU provided.
+pub async fn read_vehicle_info(channel: &IsoTpChannel<'_>) -> VehicleInfo {
+    let mut info = VehicleInfo::default();
+
+    for info_type in [INFO_VIN, INFO_CALIBRATION_ID, INFO_CVN, INFO_ECU_NAME] {
+        match channel.request(&[0x09, info_type], RESPONSE_TIMEOUT).await {
+            Ok(payload) => parse_info_response(&payload, &mut info),
+            Err(e) => eprintln!("Error reading mode 09 InfoType {:02X}: {}", info_type, e),
+        }
+    }
+
+    info
+}
+
+/// Decode a mode 09 response payload into `info`.
+///
+/// `bytes` is the reassembled ISO-TP payload: `0x49`, the InfoType, the number of data
+/// items and then the items themselves.
+pub fn parse_info_response(bytes: &[u8], info: &mut VehicleInfo) {
+    let [0x49, info_type, _count, items @ ..] = bytes else {
+        return;
+    };
+
+    match *info_type {
+        INFO_VIN => {
+            // Some ECUs pad the 17 characters with leading zero bytes.
+            let vin: String = items
+                .iter()
+                .filter(|b| b.is_ascii_alphanumeric())
+                .map(|b| *b as char)
+                .collect();
+            if !vin.is_empty() {
+                info.vin = Some(vin);
+            }
+        }
+        INFO_CALIBRATION_ID => {
+            info.calibration_ids = items.chunks(16).filter_map(ascii_item).collect();
+        }
+        INFO_CVN => {
+            info.cvns = items
+                .chunks_exact(4)
+                .map(|cvn| cvn.iter().map(|b| format!("{:02X}", b)).collect())
+                .collect();
+        }
+        INFO_ECU_NAME => {
+            info.ecu_name = ascii_item(items);
+        }
+        _ => {}
+    }
+}
+
+/// Turn a zero-padded ASCII field into a string, or `None` if it is blank.
+fn ascii_item(bytes: &[u8]) -> Option<String> {
+    let text: String = bytes
+        .iter()
+        .filter(|b| b.is_ascii_graphic() || **b == b' ')
+        .map(|b| *b as char)
+        .collect();
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(info_type: u8, count: u8, items: &[u8]) -> Vec<u8> {
+        let mut payload = vec![0x49, info_type, count];
+        payload.extend_from_slice(items);
+        payload
+    }
+
+    #[test]
+    fn vin_skips_the_count_and_padding() {
+        let mut info = VehicleInfo::default();
+        parse_info_response(
+            &payload(INFO_VIN, 0x01, b"\0\0\0WVWZZZ1KZ6W000001"),
+            &mut info,
+        );
+        assert_eq!(info.vin.as_deref(), Some("WVWZZZ1KZ6W000001"));
+
+        // A blank VIN keeps the one read before
+        parse_info_response(&payload(INFO_VIN, 0x01, &[0; 17]), &mut info);
+        assert_eq!(info.vin.as_deref(), Some("WVWZZZ1KZ6W000001"));
+    }
+
+    #[test]
+    fn calibration_ids_are_16_byte_items() {
+        let mut info = VehicleInfo::default();
+        parse_info_response(
+            &payload(
+                INFO_CALIBRATION_ID,
+                0x02,
+                b"06A906032HN\0\0\0\0\0SW 1037\0\0\0\0\0\0\0\0\0",
+            ),
+            &mut info,
+        );
+        assert_eq!(info.calibration_ids, vec!["06A906032HN", "SW 1037"]);
+    }
+
+    #[test]
+    fn cvns_are_hex_and_trailing_bytes_are_ignored() {
+        let mut info = VehicleInfo::default();
+        parse_info_response(
+            &payload(
+                INFO_CVN,
+                0x02,
+                &[0x17, 0x91, 0xBC, 0x82, 0x00, 0x00, 0x0A, 0xFF, 0x00, 0x00],
+            ),
+            &mut info,
+        );
+        assert_eq!(info.cvns, vec!["1791BC82", "00000AFF"]);
+    }
+
+    #[test]
+    fn ecu_name_drops_the_separator_and_padding() {
+        let mut info = VehicleInfo::default();
+        parse_info_response(
+            &payload(INFO_ECU_NAME, 0x01, b"ECM\0-EngineControl\0\0"),
+            &mut info,
+        );
+        assert_eq!(info.ecu_name.as_deref(), Some("ECM-EngineControl"));
+    }
+
+    #[test]
+    fn other_payloads_are_ignored() {
+        let mut info = VehicleInfo::default();
+        parse_info_response(&[0x49, INFO_VIN], &mut info);
+        parse_info_response(&[0x41, INFO_VIN, 0x01, b'W'], &mut info);
+        parse_info_response(&payload(0x08, 0x01, b"ABCD"), &mut info);
+        assert_eq!(info.vin, None);
+        assert!(info.calibration_ids.is_empty());
+    }
+}
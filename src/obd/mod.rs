@@ -1,5 +1,19 @@
 // This file contains the definitions and implementations related to OBD (On-Board Diagnostics) communication.
 // It includes functions for sending and receiving OBD requests and responses.
 
+pub mod addressing;
+pub mod classify;
+pub mod custom;
+pub mod discovery;
+pub mod dtc;
+pub mod formula;
+pub mod freeze_frame;
+pub mod info;
+pub mod multi_pid;
+pub mod pids;
+pub mod readiness;
 pub mod request;
 pub mod response;
+pub mod schedule;
+pub mod tracker;
+pub mod uds;
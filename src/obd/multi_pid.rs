@@ -0,0 +1,407 @@
+// Multi-PID mode 01 requests: up to six PIDs per request, the combined answer split into
+// one payload per PID, and fallback to single-PID requests for ECUs that reject them.
+
+use crate::obd::addressing::Addressing;
+use crate::obd::request::{SignalRequest, send_service_request};
+use socketcan::tokio::CanSocket;
+use std::collections::BTreeSet;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// PIDs a mode 01 request may carry (SAE J1979).
+pub const MAX_PIDS_PER_REQUEST: usize = 6;
+
+/// Unanswered or rejected multi-PID requests in a row after which only single PIDs are sent.
+const MAX_FAILURES: u32 = 3;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum MultiPidError {
+    #[error("Response contains PID {0:02X} of unknown length")]
+    UnknownLength(u8),
+    #[error("Response for PID {pid:02X} has {got} data bytes, {expected} needed")]
+    Truncated {
+        pid: u8,
+        expected: usize,
+        got: usize,
+    },
+}
+
+/// Number of data bytes the ECU sends after a mode 01 PID, for the PIDs that can be
+/// requested together with others.
+pub fn response_len(pid: u8) -> Option<usize> {
+    match pid {
+        0x00 | 0x20 | 0x40 | 0x60 | 0x80 | 0xA0 | 0xC0 => Some(4),
+        0x01 | 0x41 | 0x4F | 0x50 => Some(4),
+        0x02 | 0x03 => Some(2),
+        0x04..=0x0B | 0x0D..=0x0F | 0x11..=0x13 | 0x1C..=0x1E => Some(1),
+        0x0C | 0x10 | 0x14..=0x1B | 0x1F | 0x21..=0x23 => Some(2),
+        0x24..=0x2B | 0x34..=0x3B => Some(4),
+        0x2C..=0x30 | 0x33 | 0x45..=0x4C | 0x51 | 0x52 | 0x5A..=0x5C | 0x5F => Some(1),
+        0x31 | 0x32 | 0x3C..=0x3F | 0x42..=0x44 | 0x4D | 0x4E | 0x53..=0x59 => Some(2),
+        0x5D | 0x5E | 0xA2 => Some(2),
+        0x69 => Some(7),
+        0x6B | 0x73 | 0x74 | 0x77 | 0x83 => Some(5),
+        0x6F => Some(3),
+        0x75 | 0x76 | 0x7A | 0x7B => Some(7),
+        0x7C => Some(9),
+        0xA4..=0xA6 => Some(4),
+        _ => None,
+    }
+}
+
+/// Mode 01 PIDs requested together.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PidBatch {
+    pub pids: Vec<u8>,
+}
+
+impl PidBatch {
+    /// The request payload: `01` followed by every PID.
+    pub fn payload(&self) -> Vec<u8> {
+        let mut payload = vec![0x01];
+        payload.extend_from_slice(&self.pids);
+        payload
+    }
+}
+
+/// Split a positive mode 01 response into one `41 <PID> <data>` payload per PID.
+///
+/// ECUs leave out the PIDs they don't support, so the records are read in the order they
+/// were sent, using `response_len` to find where each one ends. A payload with a single
+/// record, or one that starts with a PID of unknown length, is returned unchanged.
+///
+/// # Returns
+///
+/// Returns one payload per PID, or an error if a later record can't be delimited.
+pub fn split_response(response: &[u8]) -> Result<Vec<Vec<u8>>, MultiPidError> {
+    let [0x41, first, ..] = response else {
+        return Ok(vec![response.to_vec()]);
+    };
+    match response_len(*first) {
+        Some(len) if response.len() > 2 + len => {}
+        _ => return Ok(vec![response.to_vec()]),
+    }
+
+    let mut records = Vec::new();
+    let mut rest = &response[1..];
+    while let [pid, data @ ..] = rest {
+        let len = response_len(*pid).ok_or(MultiPidError::UnknownLength(*pid))?;
+        if data.len() < len {
+            return Err(MultiPidError::Truncated {
+                pid: *pid,
+                expected: len,
+                got: data.len(),
+            });
+        }
+        let mut record = vec![0x41, *pid];
+        record.extend_from_slice(&data[..len]);
+        records.push(record);
+        rest = &data[len..];
+    }
+
+    Ok(records)
+}
+
+struct PendingBatch {
+    pids: Vec<u8>,
+    deadline: Instant,
+}
+
+/// Sends several mode 01 PIDs per request while the engine ECU answers them, and falls back
+/// to single-PID requests once it has rejected or ignored `MAX_FAILURES` of them in a row.
+/// With known PID support only batches with more than one supported PID count, since ECUs
+/// leave out the others; without it every batch that isn't fully answered counts.
+///
+/// Responses arrive through the regular receive loop, which passes the engine ECU's payloads
+/// to `received` before splitting them.
+pub struct MultiPidClient {
+    max_pids: usize,
+    timeout: Duration,
+    supported: Option<BTreeSet<u8>>,
+    pending: Vec<PendingBatch>,
+    failures: u32,
+}
+
+impl MultiPidClient {
+    /// # Arguments
+    ///
+    /// * `max_pids` - PIDs per request, at most `MAX_PIDS_PER_REQUEST`; 1 disables batching.
+    /// * `timeout` - How long to wait for the answer to a batch.
+    /// * `supported` - The PIDs reported by discovery, or `None` if they are unknown.
+    pub fn new(max_pids: usize, timeout: Duration, supported: Option<BTreeSet<u8>>) -> Self {
+        MultiPidClient {
+            max_pids: max_pids.clamp(1, MAX_PIDS_PER_REQUEST),
+            timeout,
+            supported,
+            pending: Vec::new(),
+            failures: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_pids > 1
+    }
+
+    /// Group the mode 01 requests to the configured request ID into batches.
+    ///
+    /// # Returns
+    ///
+    /// Returns the batches of two or more PIDs and the requests to send on their own: those
+    /// for other services or headers, PIDs of unknown response length and leftovers.
+    pub fn batch(&self, requests: Vec<SignalRequest>) -> (Vec<PidBatch>, Vec<SignalRequest>) {
+        if !self.is_enabled() {
+            return (Vec::new(), requests);
+        }
+
+        let (batchable, mut singles): (Vec<_>, Vec<_>) =
+            requests.into_iter().partition(|request| {
+                request.service == 0x01
+                    && request.header.is_none()
+                    && response_len(request.pid as u8).is_some()
+            });
+
+        let mut batches = Vec::new();
+        for chunk in batchable.chunks(self.max_pids) {
+            match chunk {
+                [single] => singles.push(*single),
+                _ => batches.push(PidBatch {
+                    pids: chunk.iter().map(|request| request.pid as u8).collect(),
+                }),
+            }
+        }
+
+        (batches, singles)
+    }
+
+    /// Send `batch` to the configured request ID.
+    pub async fn send(
+        &mut self,
+        socket: &CanSocket,
+        addressing: Addressing,
+        batch: &PidBatch,
+    ) -> socketcan::Result<()> {
+        send_service_request(socket, addressing.request_id(), &batch.payload()).await?;
+        self.sent(batch, Instant::now());
+        Ok(())
+    }
+
+    /// Wait for the answer to `batch`, sent at `now`; a repeated batch replaces the one
+    /// still waiting for an answer.
+    fn sent(&mut self, batch: &PidBatch, now: Instant) {
+        self.pending.retain(|pending| pending.pids != batch.pids);
+        self.pending.push(PendingBatch {
+            pids: batch.pids.clone(),
+            deadline: now + self.timeout,
+        });
+    }
+
+    /// Match a reassembled payload of the engine ECU, before it is split, to a pending batch.
+    ///
+    /// An answer with two or more PIDs confirms that the ECU supports batching. ECUs leave
+    /// out PIDs they don't support, so an answer with a single PID only counts as a rejection
+    /// if discovery reported more than one PID of the batch as supported; without discovery
+    /// any answer missing a PID counts. A negative response to mode 01 always counts as a
+    /// rejection.
+    pub fn received(&mut self, payload: &[u8]) {
+        match payload {
+            [0x41, first, ..] => {
+                let Some(index) = self.pending.iter().position(|p| p.pids.contains(first)) else {
+                    return;
+                };
+                let batch = self.pending.remove(index);
+                let answered = split_response(payload).map_or(0, |records| records.len());
+                if self.is_rejection(&batch, answered) {
+                    self.failed();
+                } else if answered > 1 {
+                    self.failures = 0;
+                }
+            }
+            [0x7F, 0x01, ..] if !self.pending.is_empty() => {
+                self.pending.remove(0);
+                self.failed();
+            }
+            _ => {}
+        }
+    }
+
+    /// Count every batch whose deadline has passed as ignored by the ECU, unless discovery
+    /// reported at most one of its PIDs as supported.
+    pub fn expire(&mut self, now: Instant) {
+        let (expired, pending) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition::<Vec<_>, _>(|pending| pending.deadline < now);
+        self.pending = pending;
+        for batch in expired {
+            if self.is_rejection(&batch, 0) {
+                self.failed();
+            }
+        }
+    }
+
+    /// Whether an answer with `answered` of the PIDs in `batch` means the ECU did not
+    /// answer the batch as a whole.
+    fn is_rejection(&self, batch: &PendingBatch, answered: usize) -> bool {
+        match &self.supported {
+            Some(supported) => {
+                answered < 2
+                    && batch
+                        .pids
+                        .iter()
+                        .filter(|pid| supported.contains(pid))
+                        .count()
+                        > 1
+            }
+            None => answered < batch.pids.len(),
+        }
+    }
+
+    fn failed(&mut self) {
+        self.failures += 1;
+        if self.failures >= MAX_FAILURES && self.is_enabled() {
+            eprintln!("ECU does not answer multi-PID requests, falling back to single PIDs");
+            self.max_pids = 1;
+            self.pending.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obd::pids::builtin_signals;
+
+    #[test]
+    fn combined_response_is_split_per_pid() {
+        let response = [0x41, 0x0C, 0x1A, 0xF8, 0x0D, 0x32, 0x05, 0x7B];
+        assert_eq!(
+            split_response(&response),
+            Ok(vec![
+                vec![0x41, 0x0C, 0x1A, 0xF8],
+                vec![0x41, 0x0D, 0x32],
+                vec![0x41, 0x05, 0x7B],
+            ])
+        );
+        // Single-PID answers and PIDs of unknown length pass through unchanged
+        assert_eq!(
+            split_response(&[0x41, 0x0C, 0x1A, 0xF8]),
+            Ok(vec![vec![0x41, 0x0C, 0x1A, 0xF8]])
+        );
+        assert_eq!(
+            split_response(&[0x41, 0x70, 0x01, 0x02]),
+            Ok(vec![vec![0x41, 0x70, 0x01, 0x02]])
+        );
+        // EGR: support byte, commanded and actual EGR and EGR error of sensors A and B
+        let response = [
+            0x41, 0x69, 0x05, 0x80, 0x80, 0x70, 0x00, 0x00, 0x00, 0x0D, 0x32,
+        ];
+        assert_eq!(
+            split_response(&response),
+            Ok(vec![
+                vec![0x41, 0x69, 0x05, 0x80, 0x80, 0x70, 0x00, 0x00, 0x00],
+                vec![0x41, 0x0D, 0x32],
+            ])
+        );
+        assert_eq!(
+            split_response(&[0x41, 0x0D, 0x32, 0x0C, 0x1A]),
+            Err(MultiPidError::Truncated {
+                pid: 0x0C,
+                expected: 2,
+                got: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn response_lengths_cover_the_decoded_bytes() {
+        for signal in builtin_signals() {
+            if let Some(len) = response_len(signal.request.pid as u8) {
+                assert!(len >= signal.formula.data_len(), "{}", signal.key);
+            }
+        }
+    }
+
+    #[test]
+    fn batches_hold_at_most_the_configured_pids() {
+        let client = MultiPidClient::new(6, Duration::from_millis(100), None);
+        let requests = (0x04..=0x10).map(SignalRequest::mode01).collect();
+        let (batches, singles) = client.batch(requests);
+
+        assert_eq!(batches.len(), 2);
+        assert_eq!(
+            batches[0].payload(),
+            vec![0x01, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09]
+        );
+        assert_eq!(batches[1].pids, vec![0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F]);
+        // The leftover PID is sent on its own
+        assert_eq!(singles, vec![SignalRequest::mode01(0x10)]);
+    }
+
+    /// Send one batch of `pids` and feed `answer` to the client, or let the batch time out.
+    fn exchange(client: &mut MultiPidClient, pids: &[u8], answer: Option<&[u8]>) {
+        let requests = pids.iter().map(|pid| SignalRequest::mode01(*pid)).collect();
+        let (batches, _) = client.batch(requests);
+        let now = Instant::now();
+        for batch in &batches {
+            client.sent(batch, now);
+        }
+        match answer {
+            Some(answer) => client.received(answer),
+            None => client.expire(now + Duration::from_secs(1)),
+        }
+    }
+
+    #[test]
+    fn repeated_rejections_fall_back_to_single_pids() {
+        let supported = [0x0C, 0x0D].into_iter().collect();
+        let mut client = MultiPidClient::new(6, Duration::from_millis(100), Some(supported));
+        for _ in 0..MAX_FAILURES {
+            assert!(client.is_enabled());
+            // Only the first PID answered although both are supported
+            exchange(&mut client, &[0x0C, 0x0D], Some(&[0x41, 0x0C, 0x1A, 0xF8]));
+        }
+
+        assert!(!client.is_enabled());
+        let (batches, singles) = client.batch(vec![
+            SignalRequest::mode01(0x0C),
+            SignalRequest::mode01(0x0D),
+        ]);
+        assert!(batches.is_empty());
+        assert_eq!(singles.len(), 2);
+    }
+
+    #[test]
+    fn omitted_unsupported_pids_are_not_a_rejection() {
+        let supported = [0x0C].into_iter().collect();
+        let mut client = MultiPidClient::new(6, Duration::from_millis(100), Some(supported));
+        for _ in 0..MAX_FAILURES {
+            exchange(&mut client, &[0x0C, 0x0D], Some(&[0x41, 0x0C, 0x1A, 0xF8]));
+            exchange(&mut client, &[0x0C, 0x0D], None);
+        }
+        assert!(client.is_enabled());
+    }
+
+    #[test]
+    fn silent_ecu_without_discovery_falls_back_to_single_pids() {
+        let mut client = MultiPidClient::new(6, Duration::from_millis(100), None);
+        for _ in 0..MAX_FAILURES {
+            assert!(client.is_enabled());
+            exchange(&mut client, &[0x0C, 0x0D], None);
+        }
+        assert!(!client.is_enabled());
+
+        // A full answer keeps batching enabled, an answer missing a PID does not
+        let mut client = MultiPidClient::new(6, Duration::from_millis(100), None);
+        for _ in 0..MAX_FAILURES {
+            exchange(
+                &mut client,
+                &[0x0C, 0x0D],
+                Some(&[0x41, 0x0C, 0x1A, 0xF8, 0x0D, 0x32]),
+            );
+        }
+        assert!(client.is_enabled());
+        for _ in 0..MAX_FAILURES {
+            exchange(&mut client, &[0x0C, 0x0D], Some(&[0x41, 0x0C, 0x1A, 0xF8]));
+        }
+        assert!(!client.is_enabled());
+    }
+}
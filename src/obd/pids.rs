@@ -0,0 +1,343 @@
+// Built-in mode 01 PID definitions and their scaling formulas (SAE J1979).
+
+use crate::obd::request::SignalRequest;
+use crate::vehicle::signals::{Decoder, DisplayGroup, Formula, SignalDefinition};
+
+use DisplayGroup::*;
+
+/// Data byte `index` after the PID (`A` = 0, `B` = 1, ...).
+fn byte(data: &[u8], index: usize) -> Option<f64> {
+    data.get(index).map(|b| *b as f64)
+}
+
+/// Big-endian 16-bit value starting at data byte `index`, e.g. `256 * A + B`.
+fn word(data: &[u8], index: usize) -> Option<f64> {
+    Some(byte(data, index)? * 256.0 + byte(data, index + 1)?)
+}
+
+/// Signed (two's complement) 16-bit value starting at data byte `index`.
+fn signed_word(data: &[u8], index: usize) -> Option<f64> {
+    Some(i16::from_be_bytes([*data.get(index)?, *data.get(index + 1)?]) as f64)
+}
+
+fn percent(data: &[u8]) -> Option<f64> {
+    Some(byte(data, 0)? * 100.0 / 255.0)
+}
+
+fn temperature(data: &[u8]) -> Option<f64> {
+    Some(byte(data, 0)? - 40.0)
+}
+
+fn fuel_trim(data: &[u8]) -> Option<f64> {
+    Some((byte(data, 0)? - 128.0) * 100.0 / 128.0)
+}
+
+fn o2_voltage(data: &[u8]) -> Option<f64> {
+    Some(byte(data, 0)? / 200.0)
+}
+
+/// Equivalence ratio of a wideband O2 sensor (PIDs 0x24–0x2B and 0x34–0x3B).
+fn o2_lambda(data: &[u8]) -> Option<f64> {
+    Some(word(data, 0)? / 32768.0)
+}
+
+/// Current of a wideband O2 sensor (PIDs 0x34–0x3B).
+fn o2_current(data: &[u8]) -> Option<f64> {
+    Some(word(data, 2)? / 256.0 - 128.0)
+}
+
+fn catalyst_temperature(data: &[u8]) -> Option<f64> {
+    Some(word(data, 0)? / 10.0 - 40.0)
+}
+
+/// PID, key, display name, topic suffix, unit, precision, display group, number of data
+/// bytes read and decoder.
+type Builtin = (
+    u8,
+    &'static str,
+    &'static str,
+    &'static str,
+    &'static str,
+    usize,
+    DisplayGroup,
+    usize,
+    Decoder,
+);
+
+#[rustfmt::skip]
+const BUILTIN: &[Builtin] = &[
+    // Engine and Performance
+    (0x04, "engine_load", "Engine Load", "ELD", "%", 1, Engine, 1, percent),
+    (0x0C, "engine_rpm", "Engine RPM", "RPM", "RPM", 2, Engine, 2, |d| Some(word(d, 0)? / 4.0)),
+    (0x0D, "vehicle_speed", "Speed", "VSS", "km/h", 0, Engine, 1, |d| byte(d, 0)),
+    (0x0E, "timing_advance", "Timing Adv", "TAD", "°", 1, Engine, 1, |d| Some(byte(d, 0)? / 2.0 - 64.0)),
+    (0x43, "absolute_load", "Abs Load", "ALD", "%", 1, Engine, 2, |d| Some(word(d, 0)? * 100.0 / 255.0)),
+    (0x42, "control_module_voltage", "Mod Volt", "BAT", "V", 2, Engine, 2, |d| Some(word(d, 0)? / 1000.0)),
+    (0xA4, "actual_gear", "Gear Ratio", "GER", "", 3, Engine, 4, |d| Some(word(d, 2)? / 1000.0)),
+    // Throttle and Pedal
+    (0x11, "throttle_pos", "Throttle", "TPS", "%", 1, Throttle, 1, percent),
+    (0x45, "relative_throttle_pos", "Rel Throttle", "RTP", "%", 1, Throttle, 1, percent),
+    (0x47, "absolute_throttle_pos_b", "Abs Thrtle B", "TPB", "%", 1, Throttle, 1, percent),
+    (0x48, "absolute_throttle_pos_c", "Abs Thrtle C", "TPC", "%", 1, Throttle, 1, percent),
+    (0x49, "accelerator_pedal_pos_d", "Pedal D", "APD", "%", 1, Throttle, 1, percent),
+    (0x4A, "accelerator_pedal_pos_e", "Pedal E", "APE", "%", 1, Throttle, 1, percent),
+    (0x4B, "accelerator_pedal_pos_f", "Pedal F", "APF", "%", 1, Throttle, 1, percent),
+    (0x4C, "commanded_throttle_actuator", "CMD Throttle", "CTA", "%", 1, Throttle, 1, percent),
+    // Temperature
+    (0x05, "coolant_temp", "Cool Temp", "CLT", "°C", 0, Temperature, 1, temperature),
+    (0x0F, "intake_temp", "Intake Temp", "MAT", "°C", 0, Temperature, 1, temperature),
+    (0x46, "ambient_temp", "Amb Temp", "AMB", "°C", 0, Temperature, 1, temperature),
+    (0x5C, "engine_oil_temp", "Oil Temp", "OIT", "°C", 0, Temperature, 1, temperature),
+    (0x3C, "catalyst_temp_b1s1", "Cat B1S1", "CT1", "°C", 1, Temperature, 2, catalyst_temperature),
+    (0x3D, "catalyst_temp_b2s1", "Cat B2S1", "CT2", "°C", 1, Temperature, 2, catalyst_temperature),
+    (0x3E, "catalyst_temp_b1s2", "Cat B1S2", "CT3", "°C", 1, Temperature, 2, catalyst_temperature),
+    (0x3F, "catalyst_temp_b2s2", "Cat B2S2", "CT4", "°C", 1, Temperature, 2, catalyst_temperature),
+    // Pressure
+    (0x0A, "fuel_pressure", "Fuel Press", "FPR", "kPa", 0, Pressure, 1, |d| Some(byte(d, 0)? * 3.0)),
+    (0x0B, "intake_pressure", "Intk Press", "MAP", "kPa", 0, Pressure, 1, |d| byte(d, 0)),
+    (0x33, "baro_pressure", "Baro Press", "BAR", "kPa", 0, Pressure, 1, |d| byte(d, 0)),
+    (0x22, "fuel_rail_pressure_relative", "Rail Press Rel", "FRR", "kPa", 1, Pressure, 2, |d| Some(word(d, 0)? * 0.079)),
+    (0x23, "fuel_rail_pressure", "Rail Press", "FRL", "kPa", 0, Pressure, 2, |d| Some(word(d, 0)? * 10.0)),
+    // Air and Fuel
+    (0x10, "maf_sensor", "MAF Rate", "MAS", "g/s", 2, AirFuel, 2, |d| Some(word(d, 0)? / 100.0)),
+    (0x44, "command_equiv_ratio", "CMD AFR", "CER", "λ", 3, AirFuel, 2, |d| Some(word(d, 0)? / 32768.0)),
+    (0x2F, "fuel_level", "Fuel Level", "FLV", "%", 1, AirFuel, 1, percent),
+    (0x5E, "engine_fuel_rate", "Fuel Rate", "FRT", "L/h", 2, AirFuel, 2, |d| Some(word(d, 0)? / 20.0)),
+    (0x52, "ethanol_fuel", "Ethanol", "ETH", "%", 1, AirFuel, 1, percent),
+    (0x5D, "fuel_injection_timing", "Inj Timing", "FIT", "°", 2, AirFuel, 2, |d| Some(word(d, 0)? / 128.0 - 210.0)),
+    // O2 Sensors
+    (0x14, "o2_sensor_voltage_b1s1", "O2 B1S1", "O21", "V", 3, Oxygen, 1, o2_voltage),
+    (0x15, "o2_sensor_voltage_b1s2", "O2 B1S2", "O22", "V", 3, Oxygen, 1, o2_voltage),
+    (0x16, "o2_sensor_voltage_b1s3", "O2 B1S3", "O23", "V", 3, Oxygen, 1, o2_voltage),
+    (0x17, "o2_sensor_voltage_b1s4", "O2 B1S4", "O24", "V", 3, Oxygen, 1, o2_voltage),
+    (0x24, "o2_lambda_b1s1", "Lambda B1S1", "LM1", "λ", 3, Oxygen, 2, o2_lambda),
+    (0x25, "o2_lambda_b1s2", "Lambda B1S2", "LM2", "λ", 3, Oxygen, 2, o2_lambda),
+    (0x26, "o2_lambda_b1s3", "Lambda B1S3", "LM3", "λ", 3, Oxygen, 2, o2_lambda),
+    (0x27, "o2_lambda_b1s4", "Lambda B1S4", "LM4", "λ", 3, Oxygen, 2, o2_lambda),
+    (0x28, "o2_lambda_b2s1", "Lambda B2S1", "LM5", "λ", 3, Oxygen, 2, o2_lambda),
+    (0x29, "o2_lambda_b2s2", "Lambda B2S2", "LM6", "λ", 3, Oxygen, 2, o2_lambda),
+    (0x2A, "o2_lambda_b2s3", "Lambda B2S3", "LM7", "λ", 3, Oxygen, 2, o2_lambda),
+    (0x2B, "o2_lambda_b2s4", "Lambda B2S4", "LM8", "λ", 3, Oxygen, 2, o2_lambda),
+    (0x34, "o2_current_b1s1", "O2 mA B1S1", "OC1", "mA", 2, Oxygen, 4, o2_current),
+    (0x35, "o2_current_b1s2", "O2 mA B1S2", "OC2", "mA", 2, Oxygen, 4, o2_current),
+    (0x36, "o2_current_b1s3", "O2 mA B1S3", "OC3", "mA", 2, Oxygen, 4, o2_current),
+    (0x37, "o2_current_b1s4", "O2 mA B1S4", "OC4", "mA", 2, Oxygen, 4, o2_current),
+    (0x38, "o2_current_b2s1", "O2 mA B2S1", "OC5", "mA", 2, Oxygen, 4, o2_current),
+    (0x39, "o2_current_b2s2", "O2 mA B2S2", "OC6", "mA", 2, Oxygen, 4, o2_current),
+    (0x3A, "o2_current_b2s3", "O2 mA B2S3", "OC7", "mA", 2, Oxygen, 4, o2_current),
+    (0x3B, "o2_current_b2s4", "O2 mA B2S4", "OC8", "mA", 2, Oxygen, 4, o2_current),
+    // Fuel Trim
+    (0x06, "fuel_trim_short_b1", "ST Trim B1", "FST", "%", 1, FuelTrim, 1, fuel_trim),
+    (0x07, "fuel_trim_long_b1", "LT Trim B1", "FLT", "%", 1, FuelTrim, 1, fuel_trim),
+    (0x08, "fuel_trim_short_b2", "ST Trim B2", "FS2", "%", 1, FuelTrim, 1, fuel_trim),
+    (0x09, "fuel_trim_long_b2", "LT Trim B2", "FL2", "%", 1, FuelTrim, 1, fuel_trim),
+    // EGR and Evaporative System
+    (0x2C, "commanded_egr", "CMD EGR", "EGR", "%", 1, Emissions, 1, percent),
+    (0x2D, "egr_error", "EGR Error", "EGE", "%", 1, Emissions, 1, fuel_trim),
+    (0x2E, "commanded_evap_purge", "CMD Purge", "EVP", "%", 1, Emissions, 1, percent),
+    (0x32, "evap_system_vapor_pressure", "Evap Press", "EVV", "Pa", 2, Emissions, 2, |d| Some(signed_word(d, 0)? / 4.0)),
+    // Counters and Timers
+    (0x1F, "engine_run_time", "Run Time", "ERT", "s", 0, Counters, 2, |d| word(d, 0)),
+    (0x21, "distance_with_mil", "Dist MIL", "MIL", "km", 0, Counters, 2, |d| word(d, 0)),
+    (0x4D, "time_with_mil", "Time MIL", "TMI", "min", 0, Counters, 2, |d| word(d, 0)),
+    (0x30, "warmups_since_codes_cleared", "Warm-ups Clr", "WUC", "", 0, Counters, 1, |d| byte(d, 0)),
+    (0x31, "distance_since_codes_cleared", "Dist Clr", "DSC", "km", 0, Counters, 2, |d| word(d, 0)),
+    (0x4E, "time_since_codes_cleared", "Time Clr", "TSC", "min", 0, Counters, 2, |d| word(d, 0)),
+    (0xA6, "odometer", "Odometer", "ODO", "km", 1, Counters, 4, |d| Some((word(d, 0)? * 65536.0 + word(d, 2)?) / 10.0)),
+    // Diesel and Turbo
+    (0x69, "actual_egr", "Actual EGR", "EGA", "%", 1, Diesel, 3, |d| Some(byte(d, 2)? * 100.0 / 255.0)),
+    (0x6B, "egr_temp", "EGR Temp", "EGT", "°C", 0, Diesel, 2, |d| Some(byte(d, 1)? - 40.0)),
+    (0x73, "exhaust_pressure", "Exh Press", "EXP", "kPa", 2, Diesel, 3, |d| Some(word(d, 1)? * 0.01)),
+    (0x74, "turbo_rpm", "Turbo RPM", "TBR", "RPM", 0, Diesel, 3, |d| Some(word(d, 1)? * 10.0)),
+    (0x75, "turbo_temp_1", "Turbo Temp 1", "TB1", "°C", 1, Diesel, 5, |d| Some(word(d, 3)? / 10.0 - 40.0)),
+    (0x76, "turbo_temp_2", "Turbo Temp 2", "TB2", "°C", 1, Diesel, 5, |d| Some(word(d, 3)? / 10.0 - 40.0)),
+    (0x77, "charge_air_temp", "Charge Air", "CAT", "°C", 0, Diesel, 2, |d| Some(byte(d, 1)? - 40.0)),
+    (0xA2, "fuel_rate_mg", "Fuel Rate mg", "FRM", "mg/str", 2, Diesel, 2, |d| Some(word(d, 0)? / 32.0)),
+    (0x6F, "turbo_pressure", "Turbo Inlet", "TIP", "kPa", 0, Diesel, 2, |d| byte(d, 1)),
+    (0x70, "boost_pressure", "Boost Press", "BST", "kPa", 1, Diesel, 5, |d| Some(word(d, 3)? / 32.0)),
+    (0x7A, "dpf_pressure_b1", "DPF dP B1", "DP1", "kPa", 2, Diesel, 3, |d| Some(signed_word(d, 1)? / 100.0)),
+    (0x7B, "dpf_pressure_b2", "DPF dP B2", "DP2", "kPa", 2, Diesel, 3, |d| Some(signed_word(d, 1)? / 100.0)),
+    (0x7C, "dpf_temp", "DPF Temp", "DPT", "°C", 1, Diesel, 3, |d| Some(word(d, 1)? / 10.0 - 40.0)),
+    (0x83, "nox_sensor", "NOx", "NOX", "ppm", 0, Diesel, 3, |d| word(d, 1)),
+    (0xA5, "def_dosing", "DEF Dosing", "DEF", "%", 1, Diesel, 2, |d| Some(byte(d, 1)? / 2.0)),
+];
+
+/// The built-in mode 01 signal definitions.
+pub fn builtin_signals() -> Vec<SignalDefinition> {
+    BUILTIN
+        .iter()
+        .map(
+            |(pid, key, name, topic, unit, precision, group, len, decode)| SignalDefinition {
+                key: key.to_string(),
+                name: name.to_string(),
+                topic: topic.to_string(),
+                unit: unit.to_string(),
+                request: SignalRequest::mode01(*pid),
+                formula: Formula::Builtin {
+                    len: *len,
+                    decode: *decode,
+                },
+                precision: *precision,
+                group: *group,
+            },
+        )
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obd::response::DecodeError;
+
+    /// PID, data bytes after the PID and the expected value.
+    #[rustfmt::skip]
+    const REFERENCE: &[(u8, &[u8], f64)] = &[
+        (0x04, &[0xFF], 100.0),
+        (0x05, &[0x7B], 83.0),
+        (0x06, &[0x80], 0.0),
+        (0x07, &[0x00], -100.0),
+        (0x08, &[0xFF], 99.21875),
+        (0x09, &[0x90], 12.5),
+        (0x0A, &[0x64], 300.0),
+        (0x0B, &[0x65], 101.0),
+        (0x0C, &[0x1A, 0xF8], 1726.0),
+        (0x0D, &[0x32], 50.0),
+        (0x0E, &[0x00], -64.0),
+        (0x0F, &[0x28], 0.0),
+        (0x10, &[0x01, 0xF4], 5.0),
+        (0x11, &[0x80], 50.196),
+        (0x14, &[0xB4, 0x80], 0.9),
+        (0x15, &[0x64, 0x80], 0.5),
+        (0x16, &[0xC8, 0x80], 1.0),
+        (0x17, &[0x00, 0xFF], 0.0),
+        (0x1F, &[0x0E, 0x10], 3600.0),
+        (0x21, &[0x00, 0x64], 100.0),
+        (0x22, &[0x03, 0xE8], 79.0),
+        (0x23, &[0x01, 0x2C], 3000.0),
+        (0x2C, &[0x80], 50.196),
+        (0x2D, &[0x70], -12.5),
+        (0x2E, &[0xFF], 100.0),
+        (0x2F, &[0x40], 25.098),
+        (0x30, &[0x05], 5.0),
+        (0x31, &[0x27, 0x10], 10000.0),
+        (0x24, &[0x80, 0x00, 0x80, 0x00], 1.0),
+        (0x25, &[0x73, 0x33, 0x00, 0x00], 0.9),
+        (0x26, &[0x00, 0x00, 0x00, 0x00], 0.0),
+        (0x27, &[0xFF, 0xFF, 0x00, 0x00], 2.0),
+        (0x28, &[0x8C, 0xCD, 0x00, 0x00], 1.1),
+        (0x29, &[0x80, 0x00, 0x00, 0x00], 1.0),
+        (0x2A, &[0x40, 0x00, 0x00, 0x00], 0.5),
+        (0x2B, &[0xC0, 0x00, 0x00, 0x00], 1.5),
+        (0x32, &[0xFF, 0x38], -50.0),
+        (0x33, &[0x64], 100.0),
+        (0x34, &[0x80, 0x00, 0x80, 0x00], 0.0),
+        (0x35, &[0x80, 0x00, 0x81, 0x00], 1.0),
+        (0x36, &[0x80, 0x00, 0x7E, 0x80], -1.5),
+        (0x37, &[0x80, 0x00, 0x00, 0x00], -128.0),
+        (0x38, &[0x80, 0x00, 0xFF, 0xFF], 127.996),
+        (0x39, &[0x80, 0x00, 0x82, 0x00], 2.0),
+        (0x3A, &[0x80, 0x00, 0x80, 0x40], 0.25),
+        (0x3B, &[0x80, 0x00, 0x7F, 0x00], -1.0),
+        (0x3C, &[0x11, 0xF8], 420.0),
+        (0x3D, &[0x01, 0x90], 0.0),
+        (0x3E, &[0x00, 0x00], -40.0),
+        (0x3F, &[0x27, 0x10], 960.0),
+        (0x42, &[0x34, 0x4C], 13.388),
+        (0x43, &[0x00, 0xFF], 100.0),
+        (0x44, &[0x80, 0x00], 1.0),
+        (0x45, &[0x33], 20.0),
+        (0x46, &[0x00], -40.0),
+        (0x47, &[0x66], 40.0),
+        (0x48, &[0x99], 60.0),
+        (0x49, &[0xCC], 80.0),
+        (0x4A, &[0x00], 0.0),
+        (0x4B, &[0xFF], 100.0),
+        (0x4C, &[0x33], 20.0),
+        (0x4D, &[0x00, 0x3C], 60.0),
+        (0x4E, &[0x01, 0x2C], 300.0),
+        (0x52, &[0x19], 9.804),
+        (0x5C, &[0xD2], 170.0),
+        (0x5D, &[0x69, 0x00], 0.0),
+        (0x5E, &[0x00, 0xC8], 10.0),
+        (0x69, &[0x07, 0x80, 0xFF], 100.0),
+        (0x6B, &[0x01, 0x8C, 0x00, 0x00, 0x00], 100.0),
+        (0x6F, &[0x01, 0x62, 0x00], 98.0),
+        (0x70, &[0x01, 0x00, 0x00, 0x0F, 0xA0, 0x00, 0x00, 0x00, 0x00, 0x00], 125.0),
+        (0x73, &[0x01, 0x27, 0x74, 0x00, 0x00], 101.0),
+        (0x74, &[0x01, 0x0B, 0xB8, 0x00, 0x00], 30000.0),
+        (0x75, &[0x04, 0x00, 0x00, 0x0A, 0xF0, 0x00, 0x00], 240.0),
+        (0x76, &[0x04, 0x00, 0x00, 0x01, 0x90, 0x00, 0x00], 0.0),
+        (0x77, &[0x01, 0x50, 0x00, 0x00, 0x00], 40.0),
+        (0x7A, &[0x01, 0x01, 0xF4, 0x00, 0x00, 0x00, 0x00], 5.0),
+        (0x7B, &[0x01, 0xFF, 0x9C, 0x00, 0x00, 0x00, 0x00], -1.0),
+        (0x7C, &[0x01, 0x11, 0xF8, 0x00, 0x00], 420.0),
+        (0x83, &[0x01, 0x00, 0xC8, 0x00, 0x00], 200.0),
+        (0xA2, &[0x01, 0x00], 8.0),
+        (0xA4, &[0x01, 0x00, 0x0B, 0xB8], 3.0),
+        (0xA5, &[0x01, 0x64], 50.0),
+        (0xA6, &[0x00, 0x01, 0xE2, 0x40], 12345.6),
+    ];
+
+    fn decode(signal: &SignalDefinition, data: &[u8]) -> Option<Result<f64, DecodeError>> {
+        let mut response = vec![0x41, signal.request.pid as u8];
+        response.extend_from_slice(data);
+        signal.decode(&response)
+    }
+
+    #[test]
+    fn every_builtin_pid_has_a_reference_value() {
+        for signal in builtin_signals() {
+            assert!(
+                REFERENCE
+                    .iter()
+                    .any(|(pid, _, _)| *pid as u16 == signal.request.pid),
+                "no reference value for {}",
+                signal.key
+            );
+        }
+    }
+
+    #[test]
+    fn builtin_pids_decode_reference_values() {
+        let signals = builtin_signals();
+        for (pid, data, expected) in REFERENCE {
+            let signal = signals
+                .iter()
+                .find(|signal| signal.request.pid == *pid as u16)
+                .expect("reference PID is built in");
+            let value = decode(signal, data).unwrap().unwrap();
+            assert!(
+                (value - expected).abs() < 0.001,
+                "PID {:02X}: {} != {}",
+                pid,
+                value,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn full_scale_percentages_do_not_overflow() {
+        let signals = builtin_signals();
+        for key in ["engine_load", "throttle_pos", "commanded_egr", "fuel_level"] {
+            let signal = signals.iter().find(|signal| signal.key == key).unwrap();
+            assert_eq!(decode(signal, &[0xFF]), Some(Ok(100.0)));
+        }
+    }
+
+    #[test]
+    fn truncated_data_is_rejected() {
+        for signal in builtin_signals() {
+            let len = signal.formula.data_len();
+            let data = vec![0u8; len - 1];
+            assert_eq!(
+                decode(&signal, &data),
+                Some(Err(DecodeError::Truncated {
+                    signal: signal.key.clone(),
+                    expected: len,
+                    got: len - 1,
+                })),
+            );
+        }
+    }
+}
@@ -0,0 +1,294 @@
+// OBD monitor status and readiness: PID 0x01 (since DTCs were cleared) and PID 0x41
+// (this drive cycle), as needed for emissions inspections.
+
+use crate::obd::response::DecodeError;
+use serde_json::{Map, Value, json};
+
+/// Monitor status since DTCs were cleared, with the MIL and the number of stored DTCs.
+pub const PID_MONITOR_STATUS: u8 = 0x01;
+
+/// Monitor status this drive cycle.
+pub const PID_DRIVE_CYCLE_STATUS: u8 = 0x41;
+
+/// Monitors run continuously, in the bit order of data byte B.
+const CONTINUOUS_MONITORS: [&str; 3] = ["misfire", "fuel_system", "components"];
+
+/// Monitors of spark ignition engines, in the bit order of data bytes C and D.
+const SPARK_MONITORS: [Option<&str>; 8] = [
+    Some("catalyst"),
+    Some("heated_catalyst"),
+    Some("evaporative_system"),
+    Some("secondary_air_system"),
+    Some("ac_refrigerant"),
+    Some("oxygen_sensor"),
+    Some("oxygen_sensor_heater"),
+    Some("egr_system"),
+];
+
+/// Monitors of compression ignition (diesel) engines; bits 2 and 4 are reserved.
+const COMPRESSION_MONITORS: [Option<&str>; 8] = [
+    Some("nmhc_catalyst"),
+    Some("nox_scr_monitor"),
+    None,
+    Some("boost_pressure"),
+    None,
+    Some("exhaust_gas_sensor"),
+    Some("pm_filter"),
+    Some("egr_vvt_system"),
+];
+
+/// One monitor's status bits.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonitorStatus {
+    pub name: &'static str,
+    /// Supported by the vehicle for PID 0x01, enabled this drive cycle for PID 0x41.
+    pub available: bool,
+    pub complete: bool,
+}
+
+/// The decoded data bytes of PID 0x01 or 0x41.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonitorReport {
+    /// Malfunction indicator lamp; always off for PID 0x41.
+    pub mil_on: bool,
+    /// Stored emissions-related DTCs; always 0 for PID 0x41.
+    pub dtc_count: u8,
+    pub compression_ignition: bool,
+    pub monitors: Vec<MonitorStatus>,
+}
+
+impl MonitorReport {
+    /// Decode the four data bytes `A B C D` that follow the PID.
+    ///
+    /// For every monitor an available bit is followed by an incomplete bit, e.g. bits 0
+    /// and 4 of `B` for misfire or bit 0 of `C` and `D` for the catalyst.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let [a, b, c, d, ..] = *data else {
+            return None;
+        };
+        let bit = |byte: u8, index: usize| byte & (1 << index) != 0;
+
+        let compression_ignition = bit(b, 3);
+        let continuous =
+            CONTINUOUS_MONITORS
+                .iter()
+                .enumerate()
+                .map(|(index, name)| MonitorStatus {
+                    name,
+                    available: bit(b, index),
+                    complete: !bit(b, index + 4),
+                });
+        let non_continuous = if compression_ignition {
+            COMPRESSION_MONITORS
+        } else {
+            SPARK_MONITORS
+        };
+        let non_continuous = non_continuous
+            .iter()
+            .enumerate()
+            .filter_map(|(index, name)| {
+                Some(MonitorStatus {
+                    name: (*name)?,
+                    available: bit(c, index),
+                    complete: !bit(d, index),
+                })
+            });
+
+        Some(MonitorReport {
+            mil_on: bit(a, 7),
+            dtc_count: a & 0x7F,
+            compression_ignition,
+            monitors: continuous.chain(non_continuous).collect(),
+        })
+    }
+
+    /// Whether every available monitor has completed.
+    pub fn ready(&self) -> bool {
+        self.monitors
+            .iter()
+            .all(|monitor| !monitor.available || monitor.complete)
+    }
+
+    /// The available monitors that have not completed yet.
+    pub fn incomplete(&self) -> Vec<&'static str> {
+        self.monitors
+            .iter()
+            .filter(|monitor| monitor.available && !monitor.complete)
+            .map(|monitor| monitor.name)
+            .collect()
+    }
+
+    /// `available_key` is `available` for PID 0x01 and `enabled` for PID 0x41.
+    fn monitors_json(&self, available_key: &str) -> Value {
+        let monitors: Map<String, Value> = self
+            .monitors
+            .iter()
+            .map(|monitor| {
+                (
+                    monitor.name.to_string(),
+                    json!({ available_key: monitor.available, "complete": monitor.complete }),
+                )
+            })
+            .collect();
+        Value::Object(monitors)
+    }
+}
+
+/// The monitor status reported by one ECU.
+#[derive(Default, Debug)]
+pub struct Readiness {
+    /// PID 0x01: status since DTCs were cleared.
+    pub since_clear: Option<MonitorReport>,
+    /// PID 0x41: status this drive cycle.
+    pub drive_cycle: Option<MonitorReport>,
+}
+
+impl Readiness {
+    /// Whether the malfunction indicator lamp is on; `None` until PID 0x01 was answered.
+    pub fn mil_on(&self) -> Option<bool> {
+        self.since_clear.as_ref().map(|report| report.mil_on)
+    }
+
+    /// JSON readiness report, e.g.
+    /// `{"mil_on":false,"dtc_count":0,"ignition":"spark","ready":false,"incomplete":["evaporative_system"],
+    /// "monitors":{"misfire":{"available":true,"complete":true},...},"drive_cycle":{...}}`.
+    ///
+    /// `drive_cycle` is `null` if the ECU does not support PID 0x41.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` while the ECU has not answered PID 0x01.
+    pub fn to_json(&self) -> Option<String> {
+        let since_clear = self.since_clear.as_ref()?;
+
+        let drive_cycle = self.drive_cycle.as_ref().map(|report| {
+            json!({
+                "ready": report.ready(),
+                "incomplete": report.incomplete(),
+                "monitors": report.monitors_json("enabled"),
+            })
+        });
+
+        let report = json!({
+            "mil_on": since_clear.mil_on,
+            "dtc_count": since_clear.dtc_count,
+            "ignition": if since_clear.compression_ignition { "compression" } else { "spark" },
+            "ready": since_clear.ready(),
+            "incomplete": since_clear.incomplete(),
+            "monitors": since_clear.monitors_json("available"),
+            "drive_cycle": drive_cycle,
+        });
+        Some(report.to_string())
+    }
+}
+
+/// Decode a PID 0x01 or 0x41 response payload, e.g. `41 01 81 07 65 04`, into `readiness`.
+///
+/// # Returns
+///
+/// Returns an error if the payload is not a monitor status response or is too short.
+pub fn parse_readiness_response(
+    bytes: &[u8],
+    readiness: &mut Readiness,
+) -> Result<(), DecodeError> {
+    let [
+        0x41,
+        pid @ (PID_MONITOR_STATUS | PID_DRIVE_CYCLE_STATUS),
+        data @ ..,
+    ] = bytes
+    else {
+        return Err(match bytes.first() {
+            Some(service) => DecodeError::UnexpectedService(*service),
+            None => DecodeError::Empty,
+        });
+    };
+
+    let report = MonitorReport::decode(data).ok_or_else(|| DecodeError::Truncated {
+        signal: format!("monitor status {:02X}", pid),
+        expected: 4,
+        got: data.len(),
+    })?;
+    if *pid == PID_MONITOR_STATUS {
+        readiness.since_clear = Some(report);
+    } else {
+        readiness.drive_cycle = Some(report);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status<'a>(report: &'a MonitorReport, name: &str) -> &'a MonitorStatus {
+        report
+            .monitors
+            .iter()
+            .find(|monitor| monitor.name == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn spark_ignition_status_is_decoded() {
+        // MIL on with 2 DTCs; misfire, fuel system and components complete;
+        // catalyst, evap and O2 sensor supported, evap incomplete
+        let report = MonitorReport::decode(&[0x82, 0x07, 0x25, 0x04]).unwrap();
+
+        assert!(report.mil_on);
+        assert_eq!(report.dtc_count, 2);
+        assert!(!report.compression_ignition);
+        assert_eq!(
+            status(&report, "catalyst"),
+            &MonitorStatus {
+                name: "catalyst",
+                available: true,
+                complete: true,
+            }
+        );
+        assert!(!status(&report, "egr_system").available);
+        assert!(!report.ready());
+        assert_eq!(report.incomplete(), vec!["evaporative_system"]);
+    }
+
+    #[test]
+    fn compression_ignition_uses_diesel_monitors() {
+        let report = MonitorReport::decode(&[0x00, 0x0F, 0x40, 0x00]).unwrap();
+
+        assert!(report.compression_ignition);
+        assert!(status(&report, "pm_filter").available);
+        assert!(
+            report
+                .monitors
+                .iter()
+                .all(|monitor| monitor.name != "catalyst")
+        );
+        assert!(report.ready());
+    }
+
+    #[test]
+    fn drive_cycle_status_is_kept_apart() {
+        let mut readiness = Readiness::default();
+        assert_eq!(readiness.to_json(), None);
+
+        parse_readiness_response(&[0x41, 0x41, 0x00, 0x07, 0x01, 0x01], &mut readiness).unwrap();
+        assert_eq!(readiness.mil_on(), None);
+        parse_readiness_response(&[0x41, 0x01, 0x00, 0x07, 0x01, 0x00], &mut readiness).unwrap();
+        assert_eq!(readiness.mil_on(), Some(false));
+
+        let report: Value = serde_json::from_str(&readiness.to_json().unwrap()).unwrap();
+        assert_eq!(report["ready"], true);
+        assert_eq!(report["drive_cycle"]["ready"], false);
+        assert_eq!(
+            report["drive_cycle"]["monitors"]["catalyst"],
+            json!({ "enabled": true, "complete": false })
+        );
+    }
+
+    #[test]
+    fn truncated_status_is_rejected() {
+        let mut readiness = Readiness::default();
+        assert!(parse_readiness_response(&[0x41, 0x01, 0x00, 0x07], &mut readiness).is_err());
+        assert_eq!(readiness.since_clear, None);
+    }
+}
@@ -1,18 +1,77 @@
+use crate::isotp::single_frame;
+use crate::obd::addressing::Addressing;
 use socketcan::{
     CanFrame, Result,
-    embedded_can::{Frame, Id, StandardId},
+    embedded_can::{Frame, Id},
     tokio::CanSocket,
 };
 use std::io::Error as IoError;
 
-const OBD_REQUEST_ID: u16 = 0x7DF;
+/// What to send to receive a signal: the service, its PID or DID and, for requests to a
+/// specific ECU, the CAN header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SignalRequest {
+    /// Request identifier, or `None` for the configured OBD request ID.
+    pub header: Option<Id>,
+    pub service: u8,
+    pub pid: u16,
+}
+
+impl SignalRequest {
+    /// A standard mode 01 request sent with the configured addressing.
+    pub fn mode01(pid: u8) -> Self {
+        SignalRequest {
+            header: None,
+            service: 0x01,
+            pid: pid as u16,
+        }
+    }
+
+    /// The request payload: the service byte followed by the PID or DID.
+    pub fn payload(&self) -> Vec<u8> {
+        let pid = self.pid.to_be_bytes();
+        let mut payload = vec![self.service];
+        payload.extend_from_slice(&pid[2 - pid_len(self.service)..]);
+        payload
+    }
+
+    /// The data bytes after the echoed service and PID, if `response` answers this request.
+    pub fn response_data<'a>(&self, response: &'a [u8]) -> Option<&'a [u8]> {
+        let request = self.payload();
+        let (service, echo) = response.split_first()?;
+        (*service == self.service + 0x40 && echo.starts_with(&request[1..]))
+            .then(|| &echo[request.len() - 1..])
+    }
+}
+
+/// Length of the PID echoed in responses to `service`: two bytes for the DIDs of UDS
+/// ReadDataByIdentifier (0x22), one byte otherwise.
+pub fn pid_len(service: u8) -> usize {
+    if service == 0x22 { 2 } else { 1 }
+}
 
-pub async fn send_obd_request(socket: &CanSocket, pid: u8) -> Result<()> {
-    let standard_id = StandardId::new(OBD_REQUEST_ID)
-        .ok_or_else(|| IoError::new(std::io::ErrorKind::InvalidInput, "Invalid CAN ID"))?;
+/// Send `request` to its header, or to the configured request ID if it has none.
+pub async fn send_signal_request(
+    socket: &CanSocket,
+    addressing: Addressing,
+    request: &SignalRequest,
+) -> Result<()> {
+    let id = request.header.unwrap_or_else(|| addressing.request_id());
+    send_service_request(socket, id, &request.payload()).await
+}
 
-    let frame = CanFrame::new(Id::Standard(standard_id), &[0x02, 0x01, pid, 0, 0, 0, 0, 0])
-        .expect("Failed to create CAN frame");
+/// Send a single-frame request for any OBD service.
+///
+/// `id` is usually `Addressing::request_id`, and `payload` starts with the service byte,
+/// followed by its parameters (e.g. `[0x03]` for stored DTCs or `[0x09, 0x02]` for the VIN).
+pub async fn send_service_request(socket: &CanSocket, id: Id, payload: &[u8]) -> Result<()> {
+    let data = single_frame(payload).ok_or_else(|| {
+        IoError::new(
+            std::io::ErrorKind::InvalidInput,
+            "Request does not fit a single frame",
+        )
+    })?;
+    let frame = CanFrame::new(id, &data).expect("Failed to create CAN frame");
 
     socket.write_frame(frame).await?;
     Ok(())
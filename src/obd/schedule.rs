@@ -0,0 +1,283 @@
+// Polling schedule: groups of requests, each sent at its own rate.
+
+use crate::obd::request::SignalRequest;
+use std::collections::BTreeSet;
+use std::time::{Duration, Instant};
+
+/// Poll intervals without an update after which a request's values count as stale.
+const STALE_AFTER_MISSED_POLLS: u32 = 3;
+
+/// Mode 01 PIDs that are requested together at a fixed rate.
+#[derive(Debug, Clone)]
+pub struct PollGroup {
+    pub name: String,
+    pub pids: Vec<u8>,
+    /// Requests per second for every PID of the group.
+    pub rate_hz: f64,
+}
+
+/// The schedule used when the config file defines no `poll_groups`.
+pub fn default_poll_groups() -> Vec<PollGroup> {
+    vec![
+        PollGroup {
+            name: "fast".to_string(),
+            pids: vec![
+                0x0C, // Engine RPM
+                0x0D, // Vehicle speed
+                0x04, // Engine load
+                0x0E, // Timing advance
+                0x11, // Throttle position
+            ],
+            rate_hz: 50.0,
+        },
+        PollGroup {
+            name: "slow".to_string(),
+            pids: vec![
+                0x05, // Coolant temperature
+                0x06, // Short term fuel trim Bank 1
+                0x07, // Long term fuel trim Bank 1
+                0x08, // Short term fuel trim Bank 2
+                0x09, // Long term fuel trim Bank 2
+                0x0A, // Fuel pressure
+                0x0B, // Intake manifold pressure
+                0x0F, // Intake air temperature
+                0x10, // MAF sensor
+                0x14, // O2 Sensor Voltage B1S1
+                0x15, // O2 Sensor Voltage B1S2
+                0x16, // O2 Sensor Voltage B1S3
+                0x17, // O2 Sensor Voltage B1S4
+                0x1F, // Run time since engine start
+                0x21, // Distance traveled with MIL on
+                0x22, // Fuel rail pressure relative to manifold
+                0x23, // Fuel rail pressure
+                0x2C, // Commanded EGR
+                0x2D, // EGR Error
+                0x2E, // Commanded evaporative purge
+                0x2F, // Fuel Level
+                0x30, // Warm-ups since codes cleared
+                0x31, // Distance traveled since codes cleared
+                0x33, // Barometric pressure
+                0x42, // Control module voltage
+                0x43, // Absolute load value
+                0x44, // Commanded equivalence ratio
+                0x45, // Relative throttle position
+                0x46, // Ambient temperature
+                0x47, // Absolute throttle position B
+                0x48, // Absolute throttle position C
+                0x49, // Accelerator pedal position D
+                0x4A, // Accelerator pedal position E
+                0x4B, // Accelerator pedal position F
+                0x4C, // Commanded throttle actuator
+                0x4D, // Time run with MIL on
+                0x4E, // Time since trouble codes cleared
+                0x52, // Ethanol fuel %
+                0x5C, // Engine oil temperature
+                0x5E, // Engine fuel rate
+            ],
+            rate_hz: 5.0,
+        },
+        PollGroup {
+            name: "extended".to_string(),
+            pids: vec![
+                0x01, // Monitor status since DTCs cleared
+                0x24, 0x25, 0x26, 0x27, // Wideband O2 lambda B1S1-B1S4
+                0x28, 0x29, 0x2A, 0x2B, // Wideband O2 lambda B2S1-B2S4
+                0x32, // Evap system vapor pressure
+                0x34, 0x35, 0x36, 0x37, // Wideband O2 current B1S1-B1S4
+                0x38, 0x39, 0x3A, 0x3B, // Wideband O2 current B2S1-B2S4
+                0x3C, 0x3D, 0x3E, 0x3F, // Catalyst temperatures
+                0x41, // Monitor status this drive cycle
+                0x5D, // Fuel injection timing
+                0x6F, // Turbocharger compressor inlet pressure
+                0x70, // Boost pressure
+                0x7A, 0x7B, // DPF differential pressure
+                0x7C, // DPF temperature
+                0x83, // NOx sensor
+            ],
+            rate_hz: 1.0,
+        },
+    ]
+}
+
+struct ScheduledGroup {
+    requests: Vec<SignalRequest>,
+    interval: Duration,
+    next_due: Instant,
+}
+
+/// Decides which requests are due on each pass of the polling loop.
+pub struct PollScheduler {
+    groups: Vec<ScheduledGroup>,
+}
+
+impl PollScheduler {
+    /// Build the schedule, dropping PIDs the ECU does not support.
+    ///
+    /// # Arguments
+    ///
+    /// * `groups` - The configured poll groups.
+    /// * `supported` - The PIDs reported by discovery, or `None` to poll everything.
+    pub fn new(groups: &[PollGroup], supported: Option<&BTreeSet<u8>>) -> Self {
+        let now = Instant::now();
+        let groups = groups
+            .iter()
+            .map(|group| ScheduledGroup {
+                requests: group
+                    .pids
+                    .iter()
+                    .copied()
+                    .filter(|pid| supported.is_none_or(|supported| supported.contains(pid)))
+                    .map(SignalRequest::mode01)
+                    .collect(),
+                interval: Duration::from_secs_f64(1.0 / group.rate_hz),
+                next_due: now,
+            })
+            .filter(|group| !group.requests.is_empty())
+            .collect();
+
+        PollScheduler { groups }
+    }
+
+    /// Schedule `request` on its own at `rate_hz`, e.g. for a custom PID definition.
+    pub fn add(&mut self, request: SignalRequest, rate_hz: f64) {
+        self.groups.push(ScheduledGroup {
+            requests: vec![request],
+            interval: Duration::from_secs_f64(1.0 / rate_hz),
+            next_due: Instant::now(),
+        });
+    }
+
+    /// Collect the requests of every group that is due at `now` and schedule their next poll.
+    ///
+    /// A group that fell more than one interval behind is rescheduled from `now` instead
+    /// of firing repeatedly to catch up.
+    pub fn due_requests(&mut self, now: Instant) -> Vec<SignalRequest> {
+        let mut requests = Vec::new();
+
+        for group in self.groups.iter_mut().filter(|group| group.next_due <= now) {
+            requests.extend_from_slice(&group.requests);
+            group.next_due += group.interval;
+            if group.next_due <= now {
+                group.next_due = now + group.interval;
+            }
+        }
+
+        requests
+    }
+
+    /// How long values from `request` stay valid: `STALE_AFTER_MISSED_POLLS` intervals of
+    /// the fastest group sending it, but never less than `minimum`.
+    pub fn stale_after(&self, request: &SignalRequest, minimum: Duration) -> Duration {
+        self.groups
+            .iter()
+            .filter(|group| group.requests.contains(request))
+            .map(|group| group.interval * STALE_AFTER_MISSED_POLLS)
+            .min()
+            .map_or(minimum, |timeout| timeout.max(minimum))
+    }
+
+    /// When the next group becomes due, or `None` if nothing is scheduled.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.groups.iter().map(|group| group.next_due).min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(name: &str, pids: &[u8], rate_hz: f64) -> PollGroup {
+        PollGroup {
+            name: name.to_string(),
+            pids: pids.to_vec(),
+            rate_hz,
+        }
+    }
+
+    fn pids(requests: Vec<SignalRequest>) -> Vec<u16> {
+        requests.iter().map(|request| request.pid).collect()
+    }
+
+    #[test]
+    fn groups_are_due_at_their_own_rate() {
+        let groups = [
+            group("fast", &[0x0C, 0x0D], 10.0),
+            group("slow", &[0x05], 2.0),
+        ];
+        let mut scheduler = PollScheduler::new(&groups, None);
+        let start = scheduler.next_due().unwrap();
+
+        assert_eq!(pids(scheduler.due_requests(start)), vec![0x0C, 0x0D, 0x05]);
+        assert_eq!(
+            scheduler.next_due(),
+            Some(start + Duration::from_millis(100))
+        );
+        assert!(
+            scheduler
+                .due_requests(start + Duration::from_millis(99))
+                .is_empty()
+        );
+        assert_eq!(
+            pids(scheduler.due_requests(start + Duration::from_millis(100))),
+            vec![0x0C, 0x0D]
+        );
+        for ms in [200, 300, 400] {
+            scheduler.due_requests(start + Duration::from_millis(ms));
+        }
+        assert_eq!(
+            pids(scheduler.due_requests(start + Duration::from_millis(500))),
+            vec![0x0C, 0x0D, 0x05]
+        );
+    }
+
+    #[test]
+    fn late_groups_are_rescheduled_instead_of_catching_up() {
+        let mut scheduler = PollScheduler::new(&[group("fast", &[0x0C], 10.0)], None);
+        let start = scheduler.next_due().unwrap();
+        scheduler.due_requests(start);
+
+        // Half a second late: polled once, then due again one interval later
+        let late = start + Duration::from_millis(600);
+        assert_eq!(pids(scheduler.due_requests(late)), vec![0x0C]);
+        assert!(scheduler.due_requests(late).is_empty());
+        assert_eq!(
+            scheduler.next_due(),
+            Some(late + Duration::from_millis(100))
+        );
+    }
+
+    #[test]
+    fn unsupported_pids_and_empty_groups_are_dropped() {
+        let supported = [0x0C, 0x05].into_iter().collect();
+        let groups = [
+            group("fast", &[0x0C, 0x0D], 10.0),
+            group("none", &[0x0D], 1.0),
+        ];
+        let mut scheduler = PollScheduler::new(&groups, Some(&supported));
+        let start = scheduler.next_due().unwrap();
+        assert_eq!(pids(scheduler.due_requests(start)), vec![0x0C]);
+
+        assert_eq!(
+            PollScheduler::new(&groups[1..], Some(&supported)).next_due(),
+            None
+        );
+    }
+
+    #[test]
+    fn values_go_stale_after_missed_polls_of_the_fastest_group() {
+        let groups = [
+            group("fast", &[0x0C], 10.0),
+            group("slow", &[0x0C, 0x05], 1.0),
+        ];
+        let mut scheduler = PollScheduler::new(&groups, None);
+        scheduler.add(SignalRequest::mode01(0x65), 0.1);
+        let minimum = Duration::from_millis(500);
+
+        let stale_after = |pid| scheduler.stale_after(&SignalRequest::mode01(pid), minimum);
+        // 3 missed polls at 10 Hz are shorter than the minimum
+        assert_eq!(stale_after(0x0C), minimum);
+        assert_eq!(stale_after(0x05), Duration::from_secs(3));
+        assert_eq!(stale_after(0x65), Duration::from_secs(30));
+        assert_eq!(stale_after(0x0D), minimum);
+    }
+}
@@ -0,0 +1,342 @@
+// Request/response correlation: matches answers to outstanding requests and keeps
+// per-request latency, timeout and success statistics.
+
+use crate::obd::request::{SignalRequest, pid_len};
+use crate::obd::uds::{RESPONSE_PENDING, RESPONSE_PENDING_TIMEOUT};
+use serde_json::{Map, Value, json};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Identifies a request by its service and, for services that take one, its PID or DID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RequestKey {
+    pub service: u8,
+    pub pid: Option<u16>,
+}
+
+impl RequestKey {
+    pub fn new(service: u8, pid: Option<u16>) -> Self {
+        RequestKey { service, pid }
+    }
+
+    /// The request a positive response payload answers, e.g. `41 0C ..` answers `01 0C`.
+    fn from_response(payload: &[u8]) -> Option<Self> {
+        match payload {
+            [service @ (0x43 | 0x44 | 0x47 | 0x4A), ..] => {
+                Some(RequestKey::new(service - 0x40, None))
+            }
+            [service @ 0x40..=0x7E, echo @ ..] => {
+                let service = service - 0x40;
+                let pid = echo.get(..pid_len(service))?;
+                let pid = pid.iter().fold(0u16, |pid, b| pid << 8 | *b as u16);
+                Some(RequestKey::new(service, Some(pid)))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl From<&SignalRequest> for RequestKey {
+    fn from(request: &SignalRequest) -> Self {
+        RequestKey::new(request.service, Some(request.pid))
+    }
+}
+
+impl fmt::Display for RequestKey {
+    /// Formats the request bytes, e.g. `01 0C` or `03`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.pid {
+            Some(pid) => write!(
+                f,
+                "{:02X} {:0width$X}",
+                self.service,
+                pid,
+                width = pid_len(self.service) * 2
+            ),
+            None => write!(f, "{:02X}", self.service),
+        }
+    }
+}
+
+/// Counters for one request key.
+#[derive(Debug, Default, Clone)]
+pub struct RequestStats {
+    pub sent: u64,
+    /// Answers received before the request timed out.
+    pub answered: u64,
+    pub timeouts: u64,
+    /// Answers that arrived after their request had already timed out.
+    pub late: u64,
+    /// Negative responses (service 0x7F) other than response-pending.
+    pub rejected: u64,
+    total_latency: Duration,
+    pub min_latency: Option<Duration>,
+    pub max_latency: Option<Duration>,
+}
+
+impl RequestStats {
+    pub fn average_latency(&self) -> Option<Duration> {
+        (self.answered > 0).then(|| self.total_latency / self.answered as u32)
+    }
+
+    /// Share of sent requests that were answered in time, from 0.0 to 1.0.
+    pub fn success_rate(&self) -> Option<f64> {
+        (self.sent > 0).then(|| self.answered as f64 / self.sent as f64)
+    }
+
+    fn record_answer(&mut self, latency: Duration) {
+        self.answered += 1;
+        self.total_latency += latency;
+        self.min_latency = Some(self.min_latency.map_or(latency, |min| min.min(latency)));
+        self.max_latency = Some(self.max_latency.map_or(latency, |max| max.max(latency)));
+    }
+
+    fn to_json(&self) -> Value {
+        let millis = |latency: Option<Duration>| latency.map(|l| l.as_secs_f64() * 1000.0);
+        json!({
+            "sent": self.sent,
+            "answered": self.answered,
+            "timeouts": self.timeouts,
+            "late": self.late,
+            "rejected": self.rejected,
+            "success_rate": self.success_rate(),
+            "latency_ms": {
+                "avg": millis(self.average_latency()),
+                "min": millis(self.min_latency),
+                "max": millis(self.max_latency),
+            },
+        })
+    }
+}
+
+/// A request waiting for its answer.
+struct Outstanding {
+    sent: Instant,
+    deadline: Instant,
+}
+
+/// Tracks outstanding requests to one ECU and matches its responses to them.
+pub struct RequestTracker {
+    timeout: Duration,
+    outstanding: BTreeMap<RequestKey, Outstanding>,
+    stats: BTreeMap<RequestKey, RequestStats>,
+}
+
+impl RequestTracker {
+    /// # Arguments
+    ///
+    /// * `timeout` - How long each request may stay unanswered before it counts as timed out.
+    pub fn new(timeout: Duration) -> Self {
+        RequestTracker {
+            timeout,
+            outstanding: BTreeMap::new(),
+            stats: BTreeMap::new(),
+        }
+    }
+
+    /// Record that `key` was requested at `now`.
+    ///
+    /// Several keys recorded with the same `now` count as one request, e.g. the DIDs of a
+    /// multi-DID read. If the previous request for the same key is still unanswered it
+    /// counts as timed out.
+    pub fn sent(&mut self, key: RequestKey, now: Instant) {
+        let stats = self.stats.entry(key).or_default();
+        stats.sent += 1;
+        let outstanding = Outstanding {
+            sent: now,
+            deadline: now + self.timeout,
+        };
+        if self.outstanding.insert(key, outstanding).is_some() {
+            stats.timeouts += 1;
+        }
+    }
+
+    /// Match a reassembled response payload to its outstanding request.
+    ///
+    /// A negative response only names the service, so it resolves the oldest outstanding
+    /// request of that service. A response-pending answer (NRC 0x78) resolves nothing but
+    /// gives every outstanding request of the service `RESPONSE_PENDING_TIMEOUT` more.
+    pub fn received(&mut self, payload: &[u8], now: Instant) {
+        if let [0x7F, service, nrc, ..] = payload {
+            let of_service = |key: &RequestKey| key.service == *service;
+
+            if *nrc == RESPONSE_PENDING {
+                for (_, outstanding) in self
+                    .outstanding
+                    .iter_mut()
+                    .filter(|(key, _)| of_service(key))
+                {
+                    outstanding.deadline = now + RESPONSE_PENDING_TIMEOUT;
+                }
+                return;
+            }
+
+            let oldest = self
+                .outstanding
+                .iter()
+                .filter(|(key, _)| of_service(key))
+                .map(|(_, outstanding)| outstanding.sent)
+                .min();
+            if let Some(oldest) = oldest {
+                let stats = &mut self.stats;
+                self.outstanding.retain(|key, outstanding| {
+                    let rejected = of_service(key) && outstanding.sent == oldest;
+                    if rejected {
+                        stats.entry(*key).or_default().rejected += 1;
+                    }
+                    !rejected
+                });
+            }
+            return;
+        }
+
+        let Some(key) = RequestKey::from_response(payload) else {
+            return;
+        };
+        match self.outstanding.remove(&key) {
+            Some(outstanding) => self
+                .stats
+                .entry(key)
+                .or_default()
+                .record_answer(now.saturating_duration_since(outstanding.sent)),
+            None => self.stats.entry(key).or_default().late += 1,
+        }
+    }
+
+    /// Count every request whose deadline has passed as timed out.
+    pub fn expire(&mut self, now: Instant) {
+        let stats = &mut self.stats;
+        self.outstanding.retain(|key, outstanding| {
+            let expired = now > outstanding.deadline;
+            if expired {
+                stats.entry(*key).or_default().timeouts += 1;
+            }
+            !expired
+        });
+    }
+
+    /// Statistics for every request key seen so far.
+    pub fn stats(&self) -> &BTreeMap<RequestKey, RequestStats> {
+        &self.stats
+    }
+
+    /// JSON object keyed by request, e.g. `{"01 0C":{"sent":120,"answered":118,...}}`.
+    pub fn to_json(&self) -> String {
+        let stats: Map<String, Value> = self
+            .stats
+            .iter()
+            .map(|(key, stats)| (key.to_string(), stats.to_json()))
+            .collect();
+        Value::Object(stats).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    fn rpm() -> RequestKey {
+        RequestKey::new(0x01, Some(0x0C))
+    }
+
+    fn did(did: u16) -> RequestKey {
+        RequestKey::new(0x22, Some(did))
+    }
+
+    #[test]
+    fn answers_record_their_latency() {
+        let mut tracker = RequestTracker::new(TIMEOUT);
+        let now = Instant::now();
+        tracker.sent(rpm(), now);
+        tracker.received(&[0x41, 0x0C, 0x1A, 0xF8], now + Duration::from_millis(20));
+        tracker.sent(rpm(), now + Duration::from_millis(50));
+        tracker.received(&[0x41, 0x0C, 0x1A, 0xF8], now + Duration::from_millis(90));
+
+        let stats = &tracker.stats()[&rpm()];
+        assert_eq!((stats.sent, stats.answered, stats.timeouts), (2, 2, 0));
+        assert_eq!(stats.min_latency, Some(Duration::from_millis(20)));
+        assert_eq!(stats.max_latency, Some(Duration::from_millis(40)));
+        assert_eq!(stats.average_latency(), Some(Duration::from_millis(30)));
+        assert_eq!(stats.success_rate(), Some(1.0));
+    }
+
+    #[test]
+    fn expired_requests_time_out_and_late_answers_are_counted() {
+        let mut tracker = RequestTracker::new(TIMEOUT);
+        let now = Instant::now();
+        tracker.sent(rpm(), now);
+        tracker.expire(now + TIMEOUT);
+        assert_eq!(tracker.stats()[&rpm()].timeouts, 0);
+
+        tracker.expire(now + TIMEOUT + Duration::from_millis(1));
+        tracker.received(&[0x41, 0x0C, 0x1A, 0xF8], now + Duration::from_millis(150));
+        let stats = &tracker.stats()[&rpm()];
+        assert_eq!((stats.timeouts, stats.late, stats.answered), (1, 1, 0));
+        assert_eq!(stats.success_rate(), Some(0.0));
+
+        // Requesting again before the answer counts the first request as timed out
+        tracker.sent(rpm(), now);
+        tracker.sent(rpm(), now + Duration::from_millis(10));
+        assert_eq!(tracker.stats()[&rpm()].timeouts, 2);
+    }
+
+    #[test]
+    fn negative_response_rejects_the_oldest_request_of_its_service() {
+        let mut tracker = RequestTracker::new(TIMEOUT);
+        let now = Instant::now();
+        // One multi-DID read, then a single one
+        tracker.sent(did(0x1001), now);
+        tracker.sent(did(0x1002), now);
+        tracker.sent(did(0x2001), now + Duration::from_millis(10));
+        tracker.sent(rpm(), now);
+
+        tracker.received(&[0x7F, 0x22, 0x31], now + Duration::from_millis(20));
+        assert_eq!(tracker.stats()[&did(0x1001)].rejected, 1);
+        assert_eq!(tracker.stats()[&did(0x1002)].rejected, 1);
+        assert_eq!(tracker.stats()[&did(0x2001)].rejected, 0);
+        assert_eq!(tracker.stats()[&rpm()].rejected, 0);
+
+        tracker.received(&[0x62, 0x20, 0x01, 0x05], now + Duration::from_millis(30));
+        assert_eq!(tracker.stats()[&did(0x2001)].answered, 1);
+    }
+
+    #[test]
+    fn response_pending_extends_the_deadline() {
+        let mut tracker = RequestTracker::new(TIMEOUT);
+        let now = Instant::now();
+        tracker.sent(did(0x1001), now);
+        tracker.sent(rpm(), now);
+
+        tracker.received(
+            &[0x7F, 0x22, RESPONSE_PENDING],
+            now + Duration::from_millis(50),
+        );
+        tracker.expire(now + Duration::from_millis(500));
+        assert_eq!(tracker.stats()[&did(0x1001)].timeouts, 0);
+        assert_eq!(tracker.stats()[&did(0x1001)].rejected, 0);
+        assert_eq!(tracker.stats()[&rpm()].timeouts, 1);
+
+        tracker.expire(now + Duration::from_millis(50) + RESPONSE_PENDING_TIMEOUT * 2);
+        assert_eq!(tracker.stats()[&did(0x1001)].timeouts, 1);
+    }
+
+    #[test]
+    fn stats_are_keyed_by_request_bytes() {
+        let mut tracker = RequestTracker::new(TIMEOUT);
+        let now = Instant::now();
+        tracker.sent(rpm(), now);
+        tracker.sent(RequestKey::new(0x03, None), now);
+        tracker.sent(did(0xF190), now);
+        tracker.received(&[0x43, 0x00], now + Duration::from_millis(5));
+
+        let json: Value = serde_json::from_str(&tracker.to_json()).unwrap();
+        assert_eq!(json["03"]["answered"], 1);
+        assert_eq!(json["03"]["latency_ms"]["avg"], 5.0);
+        assert_eq!(json["01 0C"]["sent"], 1);
+        assert_eq!(json["01 0C"]["success_rate"], 0.0);
+        assert_eq!(json["22 F190"]["latency_ms"]["avg"], Value::Null);
+    }
+}
@@ -0,0 +1,468 @@
+// UDS (ISO 14229) ReadDataByIdentifier client: batches DIDs into physical 0x22 requests,
+// splits multi-DID responses and handles negative and response-pending answers.
+
+use crate::obd::addressing::Addressing;
+use crate::obd::request::{SignalRequest, send_service_request};
+use crate::vehicle::signals::SignalRegistry;
+use socketcan::{embedded_can::Id, tokio::CanSocket};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Service ID of ReadDataByIdentifier.
+pub const READ_DATA_BY_IDENTIFIER: u8 = 0x22;
+
+/// Negative response code asking the tester to keep waiting for the real answer.
+pub const RESPONSE_PENDING: u8 = 0x78;
+
+/// P2*: how long to wait after a response-pending answer (ISO 14229-2 default).
+pub const RESPONSE_PENDING_TIMEOUT: Duration = Duration::from_millis(5000);
+
+/// DIDs per request: the service byte and three DIDs fill a Single Frame.
+pub const MAX_DIDS_PER_REQUEST: usize = 3;
+
+/// The payloads of one response, ready for decoding.
+#[derive(Debug, Default, PartialEq)]
+pub struct UdsResponse {
+    /// One payload per answered DID, or the unchanged payload if it answers no pending read.
+    pub payloads: Vec<Vec<u8>>,
+    /// DIDs of the matched read that the ECU left out, e.g. because it doesn't support them.
+    pub unanswered: Vec<u16>,
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum UdsError {
+    #[error("ECU rejected the read of DIDs {dids:04X?}: NRC {nrc:02X}")]
+    Negative { dids: Vec<u16>, nrc: u8 },
+    #[error("Response is missing DID {0:04X}")]
+    MissingRecord(u16),
+    #[error("Response for DID {did:04X} has {got} data bytes, {expected} needed")]
+    Truncated {
+        did: u16,
+        expected: usize,
+        got: usize,
+    },
+}
+
+/// DIDs read together in one request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DidBatch {
+    /// Request identifier, or `None` for the engine ECU.
+    pub header: Option<Id>,
+    pub dids: Vec<u16>,
+    /// Data bytes of each DID, in the order of `dids`, from its registered signals.
+    pub data_lens: Vec<usize>,
+}
+
+impl DidBatch {
+    /// The request payload: `22` followed by every DID.
+    pub fn payload(&self) -> Vec<u8> {
+        let mut payload = vec![READ_DATA_BY_IDENTIFIER];
+        for did in &self.dids {
+            payload.extend_from_slice(&did.to_be_bytes());
+        }
+        payload
+    }
+}
+
+/// Group the 0x22 requests among `requests` by header into batches of at most
+/// `MAX_DIDS_PER_REQUEST` DIDs; requests for other services are ignored.
+///
+/// The records of a multi-DID answer are delimited by each DID's data length, so DIDs
+/// without a registered signal are read on their own.
+pub fn batch_reads(requests: &[SignalRequest], registry: &SignalRegistry) -> Vec<DidBatch> {
+    let mut batches: Vec<DidBatch> = Vec::new();
+
+    for request in requests
+        .iter()
+        .filter(|request| request.service == READ_DATA_BY_IDENTIFIER)
+    {
+        let Some(data_len) = registry.data_len(request) else {
+            batches.push(DidBatch {
+                header: request.header,
+                dids: vec![request.pid],
+                data_lens: vec![0],
+            });
+            continue;
+        };
+        match batches.iter_mut().find(|batch| {
+            batch.header == request.header
+                && batch.dids.len() < MAX_DIDS_PER_REQUEST
+                && batch.data_lens.iter().all(|len| *len > 0)
+        }) {
+            Some(batch) => {
+                batch.dids.push(request.pid);
+                batch.data_lens.push(data_len);
+            }
+            None => batches.push(DidBatch {
+                header: request.header,
+                dids: vec![request.pid],
+                data_lens: vec![data_len],
+            }),
+        }
+    }
+
+    batches
+}
+
+/// Split a positive multi-DID response into one `62 <DID> <data>` payload per DID.
+///
+/// The records carry no length, so each one is taken to be as long as its DID's data
+/// length. ECUs answer in the order the DIDs were requested but may leave out DIDs they
+/// don't support, so a DID that does not follow the previous record counts as unanswered.
+/// The record of the last requested DID keeps any extra bytes.
+///
+/// # Arguments
+///
+/// * `response` - The reassembled payload, starting with `0x62`.
+/// * `dids` - The DIDs of the request, in request order.
+/// * `data_lens` - The data length of each DID, in the order of `dids`.
+///
+/// # Returns
+///
+/// Returns the records and the unanswered DIDs, `UdsError::MissingRecord` for the first
+/// unanswered DID if bytes are left over that belong to no record, or
+/// `UdsError::Truncated` if a record is too short.
+pub fn split_records(
+    response: &[u8],
+    dids: &[u16],
+    data_lens: &[usize],
+) -> Result<UdsResponse, UdsError> {
+    let mut rest = response.get(1..).unwrap_or_default();
+    let mut split = UdsResponse::default();
+
+    for (index, (did, data_len)) in dids.iter().zip(data_lens).enumerate() {
+        let Some(data) = rest.strip_prefix(&did.to_be_bytes()[..]) else {
+            split.unanswered.push(*did);
+            continue;
+        };
+        if data.len() < *data_len {
+            return Err(UdsError::Truncated {
+                did: *did,
+                expected: *data_len,
+                got: data.len(),
+            });
+        }
+        let len = if index + 1 == dids.len() {
+            data.len()
+        } else {
+            *data_len
+        };
+
+        let mut record = vec![READ_DATA_BY_IDENTIFIER + 0x40];
+        record.extend_from_slice(&did.to_be_bytes());
+        record.extend_from_slice(&data[..len]);
+        split.payloads.push(record);
+        rest = &data[len..];
+    }
+
+    if let (false, Some(did)) = (rest.is_empty(), split.unanswered.first()) {
+        return Err(UdsError::MissingRecord(*did));
+    }
+    Ok(split)
+}
+
+struct PendingRead {
+    /// The identifier the answer arrives on.
+    response_id: Id,
+    dids: Vec<u16>,
+    data_lens: Vec<usize>,
+    deadline: Instant,
+}
+
+/// Sends ReadDataByIdentifier requests and matches the answers to them.
+///
+/// Responses arrive through the regular receive loop, which passes every payload to
+/// `handle_response` together with the ID it arrived on before decoding it.
+pub struct UdsClient {
+    timeout: Duration,
+    pending: Vec<PendingRead>,
+}
+
+impl UdsClient {
+    /// # Arguments
+    ///
+    /// * `timeout` - How long to wait for an answer that is not response-pending.
+    pub fn new(timeout: Duration) -> Self {
+        UdsClient {
+            timeout,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Send `batch` to its header, or physically to the engine ECU if it has none.
+    ///
+    /// # Arguments
+    ///
+    /// * `response_id` - The identifier the ECU answers on.
+    pub async fn read_data_by_identifier(
+        &mut self,
+        socket: &CanSocket,
+        addressing: Addressing,
+        batch: &DidBatch,
+        response_id: Id,
+    ) -> socketcan::Result<()> {
+        let id = batch
+            .header
+            .unwrap_or_else(|| addressing.engine_request_id());
+        send_service_request(socket, id, &batch.payload()).await?;
+        self.sent(batch, response_id, Instant::now());
+        Ok(())
+    }
+
+    /// Wait for the answer to `batch`, sent at `now`; a repeated request replaces the one
+    /// still waiting for an answer.
+    fn sent(&mut self, batch: &DidBatch, response_id: Id, now: Instant) {
+        self.pending
+            .retain(|pending| pending.response_id != response_id || pending.dids != batch.dids);
+        self.pending.push(PendingRead {
+            response_id,
+            dids: batch.dids.clone(),
+            data_lens: batch.data_lens.clone(),
+            deadline: now + self.timeout,
+        });
+    }
+
+    /// Prepare a reassembled payload received on `response_id` for decoding.
+    ///
+    /// A multi-DID answer is matched to the read of that ECU that asked for its first
+    /// record, which need not be the first DID requested, and split into one payload per DID. A response-pending answer extends the
+    /// wait for that ECU's oldest outstanding read, and other payloads are returned
+    /// unchanged.
+    ///
+    /// # Returns
+    ///
+    /// Returns the payloads to decode, or an error if the ECU rejected a read or a record
+    /// could not be delimited. The caller can still decode the unchanged payload after an
+    /// error.
+    pub fn handle_response(
+        &mut self,
+        payload: &[u8],
+        response_id: Id,
+        now: Instant,
+    ) -> Result<UdsResponse, UdsError> {
+        self.pending.retain(|pending| pending.deadline >= now);
+        let unchanged = || UdsResponse {
+            payloads: vec![payload.to_vec()],
+            unanswered: Vec::new(),
+        };
+        let oldest = self
+            .pending
+            .iter()
+            .position(|pending| pending.response_id == response_id);
+
+        match payload {
+            [0x62, did_high, did_low, ..] => {
+                let did = u16::from_be_bytes([*did_high, *did_low]);
+                match self.pending.iter().position(|pending| {
+                    pending.response_id == response_id && pending.dids.contains(&did)
+                }) {
+                    Some(index) => {
+                        let read = self.pending.remove(index);
+                        split_records(payload, &read.dids, &read.data_lens)
+                    }
+                    None => Ok(unchanged()),
+                }
+            }
+            [0x7F, READ_DATA_BY_IDENTIFIER, RESPONSE_PENDING, ..] => {
+                if let Some(index) = oldest {
+                    self.pending[index].deadline = now + RESPONSE_PENDING_TIMEOUT;
+                }
+                Ok(unchanged())
+            }
+            [0x7F, READ_DATA_BY_IDENTIFIER, nrc, ..] => match oldest {
+                Some(index) => Err(UdsError::Negative {
+                    dids: self.pending.remove(index).dids,
+                    nrc: *nrc,
+                }),
+                None => Ok(unchanged()),
+            },
+            _ => Ok(unchanged()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obd::formula::Expression;
+    use crate::vehicle::signals::{DisplayGroup, Formula, SignalDefinition};
+    use socketcan::StandardId;
+
+    fn id(raw: u16) -> Id {
+        Id::Standard(StandardId::new(raw).unwrap())
+    }
+
+    fn read(header: Option<Id>, did: u16) -> SignalRequest {
+        SignalRequest {
+            header,
+            service: READ_DATA_BY_IDENTIFIER,
+            pid: did,
+        }
+    }
+
+    /// A registry with one signal per DID, decoded from its first `len` data bytes.
+    fn registry(dids: &[(u16, usize)]) -> SignalRegistry {
+        let formula = |len: usize| match len {
+            1 => "A",
+            _ => "A*256+B",
+        };
+        SignalRegistry::new(
+            dids.iter()
+                .map(|(did, len)| SignalDefinition {
+                    key: format!("did_{:04x}", did),
+                    name: format!("DID {:04X}", did),
+                    topic: format!("{:04X}", did),
+                    unit: String::new(),
+                    request: read(None, *did),
+                    formula: Formula::Expression(Expression::parse(formula(*len)).unwrap()),
+                    precision: 0,
+                    group: DisplayGroup::Custom,
+                })
+                .collect(),
+        )
+    }
+
+    fn records(payloads: &[&[u8]], unanswered: &[u16]) -> Result<UdsResponse, UdsError> {
+        Ok(UdsResponse {
+            payloads: payloads.iter().map(|payload| payload.to_vec()).collect(),
+            unanswered: unanswered.to_vec(),
+        })
+    }
+
+    #[test]
+    fn records_are_delimited_by_data_length() {
+        // The data of DID 1234 contains the next DID, F190
+        let response = [0x62, 0x12, 0x34, 0xF1, 0x90, 0xF1, 0x90, 0x57, 0x30];
+        assert_eq!(
+            split_records(&response, &[0x1234, 0xF190], &[2, 1]),
+            records(
+                &[
+                    &[0x62, 0x12, 0x34, 0xF1, 0x90],
+                    &[0x62, 0xF1, 0x90, 0x57, 0x30]
+                ],
+                &[]
+            )
+        );
+    }
+
+    #[test]
+    fn left_out_dids_are_unanswered() {
+        let response = [0x62, 0xF1, 0x90, 0x57, 0x12, 0x35, 0x01, 0x02];
+        assert_eq!(
+            split_records(&response, &[0x1234, 0xF190, 0x1235], &[2, 1, 2]),
+            records(
+                &[&[0x62, 0xF1, 0x90, 0x57], &[0x62, 0x12, 0x35, 0x01, 0x02]],
+                &[0x1234]
+            )
+        );
+    }
+
+    #[test]
+    fn lengths_that_do_not_add_up_are_rejected() {
+        let response = [0x62, 0x12, 0x34, 0x01, 0x02, 0x03, 0xF1, 0x90, 0x57];
+        assert_eq!(
+            split_records(&response, &[0x1234, 0xF190], &[2, 1]),
+            Err(UdsError::MissingRecord(0xF190))
+        );
+        assert_eq!(
+            split_records(&response[..8], &[0x1234, 0xF190], &[3, 2]),
+            Err(UdsError::Truncated {
+                did: 0xF190,
+                expected: 2,
+                got: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn reads_are_batched_per_header() {
+        let registry = registry(&[(0x1001, 1), (0x1002, 2), (0x1003, 1), (0x1004, 1)]);
+        let requests = [
+            read(None, 0x1001),
+            read(Some(id(0x714)), 0x1002),
+            SignalRequest::mode01(0x0C),
+            read(None, 0x1002),
+            read(None, 0xF190),
+            read(None, 0x1003),
+            read(None, 0x1004),
+        ];
+
+        let batches = batch_reads(&requests, &registry);
+        let summary: Vec<_> = batches
+            .iter()
+            .map(|batch| (batch.header, batch.dids.clone(), batch.data_lens.clone()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (None, vec![0x1001, 0x1002, 0x1003], vec![1, 2, 1]),
+                // Without a signal for that ECU or DID the length is unknown, so it is read
+                // on its own
+                (Some(id(0x714)), vec![0x1002], vec![0]),
+                (None, vec![0xF190], vec![0]),
+                (None, vec![0x1004], vec![1]),
+            ]
+        );
+        assert_eq!(
+            batches[0].payload(),
+            vec![0x22, 0x10, 0x01, 0x10, 0x02, 0x10, 0x03]
+        );
+    }
+
+    #[test]
+    fn answers_are_matched_by_ecu_and_any_did() {
+        let mut client = UdsClient::new(Duration::from_millis(100));
+        let batch = DidBatch {
+            header: None,
+            dids: vec![0x1001, 0x1002],
+            data_lens: vec![1, 1],
+        };
+        let now = Instant::now();
+        client.sent(&batch, id(0x7E8), now);
+
+        // Another ECU's answer and the first DID left out
+        let other = [0x62, 0x10, 0x02, 0x05];
+        assert_eq!(
+            client.handle_response(&other, id(0x7E9), now),
+            records(&[&other], &[])
+        );
+        assert_eq!(
+            client.handle_response(&other, id(0x7E8), now),
+            records(&[&other], &[0x1001])
+        );
+        // The read is resolved, so a repeated answer passes through unchanged
+        assert_eq!(
+            client.handle_response(&other, id(0x7E8), now),
+            records(&[&other], &[])
+        );
+    }
+
+    #[test]
+    fn negative_responses_resolve_the_reads_of_their_ecu() {
+        let mut client = UdsClient::new(Duration::from_millis(100));
+        let batch = |did: u16| DidBatch {
+            header: None,
+            dids: vec![did],
+            data_lens: vec![1],
+        };
+        let now = Instant::now();
+        client.sent(&batch(0x1001), id(0x7E8), now);
+        client.sent(&batch(0x2001), id(0x7E9), now);
+
+        // Response pending extends the wait for the engine ECU's read only
+        let pending = [0x7F, 0x22, RESPONSE_PENDING];
+        assert!(client.handle_response(&pending, id(0x7E8), now).is_ok());
+        let later = now + Duration::from_millis(200);
+        assert_eq!(
+            client.handle_response(&[0x7F, 0x22, 0x31], id(0x7E9), later),
+            records(&[&[0x7F, 0x22, 0x31]], &[])
+        );
+        assert_eq!(
+            client.handle_response(&[0x7F, 0x22, 0x31], id(0x7E8), later),
+            Err(UdsError::Negative {
+                dids: vec![0x1001],
+                nrc: 0x31,
+            })
+        );
+    }
+}
@@ -0,0 +1,171 @@
+// Snapshot output: the whole vehicle state as one JSON document, for consumers that want a
+// consistent set of values instead of one topic per signal.
+
+use crate::vehicle::data::{SignalState, VehicleData};
+use crate::vehicle::signals::{SignalDefinition, SignalRegistry};
+use serde_json::{Map, Value, json};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How decoded values are published.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputMode {
+    /// One retained topic per signal, published when the value changes.
+    #[default]
+    Topics,
+    /// One JSON document with every signal at a fixed rate.
+    Snapshot,
+    Both,
+}
+
+impl OutputMode {
+    /// Parse the `mqtt_output` configuration value.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "topics" => Some(OutputMode::Topics),
+            "snapshot" => Some(OutputMode::Snapshot),
+            "both" => Some(OutputMode::Both),
+            _ => None,
+        }
+    }
+
+    pub fn publishes_topics(self) -> bool {
+        matches!(self, OutputMode::Topics | OutputMode::Both)
+    }
+
+    pub fn publishes_snapshot(self) -> bool {
+        matches!(self, OutputMode::Snapshot | OutputMode::Both)
+    }
+}
+
+/// Every signal of one ECU as `{"engine_rpm":{"value":2450.25,"unit":"RPM"},...}`.
+///
+/// Signals the ECU never reported are left out and stale values are `null`.
+pub fn signals_json(
+    registry: &SignalRegistry,
+    data: &VehicleData,
+    state: &dyn Fn(&SignalDefinition) -> SignalState,
+) -> Value {
+    let signals: Map<String, Value> = registry
+        .iter()
+        .filter_map(|signal| {
+            let value = match (state(signal), data.get(&signal.key)) {
+                // Round to the signal's precision, like the per-topic output
+                (SignalState::Valid, Some(value)) => {
+                    json!(signal.format(value).parse::<f64>().ok())
+                }
+                (SignalState::Stale, _) => Value::Null,
+                _ => return None,
+            };
+            Some((
+                signal.key.clone(),
+                json!({ "value": value, "unit": signal.unit }),
+            ))
+        })
+        .collect();
+
+    Value::Object(signals)
+}
+
+/// The snapshot document: a Unix timestamp in milliseconds, the engine ECU's signals and,
+/// if other ECUs answered, their signals keyed by response ID.
+///
+/// # Arguments
+///
+/// * `timestamp` - When the snapshot was taken.
+/// * `engine` - The engine ECU's signals, from `signals_json`.
+/// * `ecus` - The other ECUs' signals, e.g. `("7E9", signals)`.
+pub fn snapshot_json(timestamp: SystemTime, engine: Value, ecus: Vec<(String, Value)>) -> String {
+    let timestamp_ms = timestamp
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_millis() as u64);
+
+    let mut snapshot = json!({
+        "timestamp_ms": timestamp_ms,
+        "signals": engine,
+    });
+    if !ecus.is_empty() {
+        snapshot["ecus"] = Value::Object(ecus.into_iter().collect());
+    }
+
+    snapshot.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obd::pids::builtin_signals;
+    use std::time::{Duration, Instant};
+
+    const MAX_AGE: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn output_modes_select_topics_and_snapshot() {
+        let modes = [
+            ("topics", OutputMode::Topics, true, false),
+            ("snapshot", OutputMode::Snapshot, false, true),
+            ("both", OutputMode::Both, true, true),
+        ];
+        for (value, mode, topics, snapshot) in modes {
+            assert_eq!(OutputMode::parse(value), Some(mode));
+            assert_eq!(mode.publishes_topics(), topics, "{}", value);
+            assert_eq!(mode.publishes_snapshot(), snapshot, "{}", value);
+        }
+        assert_eq!(OutputMode::parse("json"), None);
+        assert_eq!(OutputMode::default(), OutputMode::Topics);
+    }
+
+    #[test]
+    fn signals_are_valid_stale_or_left_out() {
+        let registry = SignalRegistry::new(builtin_signals());
+        let start = Instant::now();
+        let now = start + MAX_AGE * 2;
+        let mut data = VehicleData::default();
+        data.set("engine_rpm", 2450.254, now);
+        data.set("coolant_temp", 88.0, start);
+
+        let signals = signals_json(&registry, &data, &|signal| {
+            data.state(&signal.key, now, MAX_AGE)
+        });
+        assert_eq!(
+            signals,
+            json!({
+                "engine_rpm": {"value": 2450.25, "unit": "RPM"},
+                "coolant_temp": {"value": null, "unit": "°C"},
+            })
+        );
+
+        let never_seen = signals_json(&registry, &VehicleData::default(), &|_| {
+            SignalState::NeverSeen
+        });
+        assert_eq!(never_seen, json!({}));
+    }
+
+    #[test]
+    fn snapshot_lists_other_ecus_only_when_they_answered() {
+        let timestamp = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        let engine = json!({"engine_rpm": {"value": 800.0, "unit": "RPM"}});
+
+        let snapshot: Value =
+            serde_json::from_str(&snapshot_json(timestamp, engine.clone(), Vec::new())).unwrap();
+        assert_eq!(
+            snapshot,
+            json!({"timestamp_ms": 1_700_000_000_123u64, "signals": engine})
+        );
+
+        let transmission = json!({"oil_temp": {"value": null, "unit": "°C"}});
+        let snapshot: Value = serde_json::from_str(&snapshot_json(
+            timestamp,
+            engine.clone(),
+            vec![("7E9".to_string(), transmission.clone())],
+        ))
+        .unwrap();
+        assert_eq!(
+            snapshot,
+            json!({
+                "timestamp_ms": 1_700_000_000_123u64,
+                "signals": engine,
+                "ecus": {"7E9": transmission},
+            })
+        );
+    }
+}
@@ -1,87 +1,109 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// Freshness of a decoded value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalState {
+    /// The ECU never answered for this value.
+    NeverSeen,
+    /// The value was decoded once but has not been updated recently.
+    Stale,
+    Valid,
+}
+
+/// A decoded value and when it was last updated.
+#[derive(Debug, Clone, Copy)]
+pub struct SignalValue {
+    pub value: f64,
+    pub updated: Instant,
+}
+
+/// The latest value of every signal decoded so far, keyed by signal key.
 #[derive(Default, Debug)]
 pub struct VehicleData {
-    // Engine and Performance
-    pub engine_load: u8,
-    pub engine_rpm: f32,
-    pub vehicle_speed: u8,
-    pub timing_advance: f32,
-    pub throttle_pos: u8,
-    pub relative_throttle_pos: u8,
-    pub absolute_throttle_pos_b: u8,
-    pub absolute_throttle_pos_c: u8,
-    pub accelerator_pedal_pos_d: u8,
-    pub accelerator_pedal_pos_e: u8,
-    pub accelerator_pedal_pos_f: u8,
-    pub commanded_throttle_actuator: u8,
+    values: BTreeMap<String, SignalValue>,
+}
+
+impl VehicleData {
+    /// Store a decoded value of the signal `key`.
+    pub fn set(&mut self, key: &str, value: f64, now: Instant) {
+        let value = SignalValue {
+            value,
+            updated: now,
+        };
+        match self.values.get_mut(key) {
+            Some(existing) => *existing = value,
+            None => {
+                self.values.insert(key.to_string(), value);
+            }
+        }
+    }
 
-    // Temperature Data
-    pub coolant_temp: i16,
-    pub intake_temp: i16,
-    pub ambient_temp: i16,
-    pub engine_oil_temp: i16,
-    pub catalyst_temp_b1s1: f32,
-    pub catalyst_temp_b2s1: f32,
+    /// The latest value of the signal `key`, stale or not.
+    pub fn get(&self, key: &str) -> Option<f64> {
+        self.values.get(key).map(|signal| signal.value)
+    }
+
+    /// Freshness of the signal `key`.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The signal key.
+    /// * `now` - The current time.
+    /// * `max_age` - How old the last update may be before the value counts as stale.
+    pub fn state(&self, key: &str, now: Instant, max_age: Duration) -> SignalState {
+        match self.values.get(key) {
+            None => SignalState::NeverSeen,
+            Some(signal) if now.saturating_duration_since(signal.updated) > max_age => {
+                SignalState::Stale
+            }
+            Some(_) => SignalState::Valid,
+        }
+    }
+}
 
-    // Pressure Data
-    pub fuel_pressure: u8,
-    pub intake_pressure: u8,
-    pub baro_pressure: u8,
-    pub fuel_rail_pressure: u16,
-    pub evap_system_vapor_pressure: f32,
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // Air and Fuel Data
-    pub maf_sensor: f32,
-    pub command_equiv_ratio: f32,
-    pub fuel_level: u8,
-    pub engine_fuel_rate: f32,
-    pub fuel_injection_timing: f32,
-    pub ethanol_fuel: u8,
+    const MAX_AGE: Duration = Duration::from_secs(5);
 
-    // O2 Sensor Data
-    pub o2_voltage: f32,
-    pub o2_current: f32,
-    pub o2_lambda: f32,
-    pub o2_sensor_voltage_b1s1: f32,
-    pub o2_sensor_voltage_b1s2: f32,
-    pub o2_sensor_voltage_b1s3: f32,
-    pub o2_sensor_voltage_b1s4: f32,
+    #[test]
+    fn state_follows_the_last_update() {
+        let mut data = VehicleData::default();
+        let start = Instant::now();
+        assert_eq!(
+            data.state("engine_rpm", start, MAX_AGE),
+            SignalState::NeverSeen
+        );
+        assert_eq!(data.get("engine_rpm"), None);
 
-    // Fuel Trim Data
-    pub fuel_trim_short_b1: f32,
-    pub fuel_trim_long_b1: f32,
-    pub fuel_trim_short_b2: f32,
-    pub fuel_trim_long_b2: f32,
+        data.set("engine_rpm", 800.0, start);
+        assert_eq!(data.state("engine_rpm", start, MAX_AGE), SignalState::Valid);
+        assert_eq!(
+            data.state("engine_rpm", start + MAX_AGE, MAX_AGE),
+            SignalState::Valid
+        );
 
-    // EGR System
-    pub commanded_egr: u8,
-    pub egr_error: f32,
-    pub commanded_evap_purge: u8,
+        let later = start + MAX_AGE + Duration::from_millis(1);
+        assert_eq!(data.state("engine_rpm", later, MAX_AGE), SignalState::Stale);
+        // A stale value is still readable
+        assert_eq!(data.get("engine_rpm"), Some(800.0));
 
-    // Additional Engine Data
-    pub control_module_voltage: f32,
-    pub absolute_load: f32,
-    pub engine_run_time: f32,
-    pub distance_with_mil: u16,
-    pub time_since_codes_cleared: u16,
-    pub warmups_since_codes_cleared: u8,
-    pub distance_since_codes_cleared: u16,
-    pub time_with_mil: u16,
+        data.set("engine_rpm", 2450.5, later);
+        assert_eq!(data.state("engine_rpm", later, MAX_AGE), SignalState::Valid);
+        assert_eq!(data.get("engine_rpm"), Some(2450.5));
+        assert_eq!(
+            data.state("coolant_temp", later, MAX_AGE),
+            SignalState::NeverSeen
+        );
+    }
 
-    // Additional Diesel/Advanced Engine Data
-    pub actual_egr: u8,
-    pub diesel_intake_flow: u8,
-    pub egr_temp: i16,
-    pub exhaust_pressure: u16,
-    pub turbo_pressure: f32,
-    pub turbo_rpm: u32,
-    pub turbo_temp_1: i16,
-    pub turbo_temp_2: i16,
-    pub charge_air_temp: i16,
-    pub dpf_pressure: f32,
-    pub dpf_temp: f32,
-    pub nox_sensor: f32,
-    pub fuel_rate_mg: f32,
-    pub actual_gear: f32,
-    pub def_dosing: f32,
-    pub odometer: f32,
+    #[test]
+    fn update_from_the_future_counts_as_valid() {
+        let mut data = VehicleData::default();
+        let start = Instant::now();
+        data.set("engine_rpm", 800.0, start + Duration::from_secs(1));
+        assert_eq!(data.state("engine_rpm", start, MAX_AGE), SignalState::Valid);
+    }
 }
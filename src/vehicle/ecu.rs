@@ -0,0 +1,62 @@
+use crate::isotp::{IsoTpConfig, Reassembler};
+use crate::obd::addressing::{Addressing, id_to_hex};
+use crate::obd::classify::NegativeResponseLog;
+use crate::obd::dtc::TroubleCodes;
+use crate::obd::freeze_frame::FreezeFrame;
+use crate::obd::readiness::Readiness;
+use crate::vehicle::data::VehicleData;
+use socketcan::embedded_can::Id;
+
+/// Everything tracked for one ECU answering on the OBD response IDs.
+#[derive(Debug)]
+pub struct EcuState {
+    pub response_id: Id,
+    /// Physical request ID of the ECU, which is also where flow control frames go.
+    pub request_id: Id,
+    /// Whether this is the engine ECU, whose data is published as the default.
+    pub is_engine: bool,
+    pub vehicle_data: VehicleData,
+    pub trouble_codes: TroubleCodes,
+    pub freeze_frame: FreezeFrame,
+    pub readiness: Readiness,
+    /// Negative response codes received from this ECU.
+    pub negative_responses: NegativeResponseLog,
+    pub reassembler: Reassembler,
+}
+
+impl EcuState {
+    /// # Arguments
+    ///
+    /// * `response_id` - The identifier the ECU answers on.
+    /// * `request_id` - The ECU's physical request ID, where flow control frames go.
+    pub fn new(
+        response_id: Id,
+        request_id: Id,
+        addressing: Addressing,
+        isotp: &IsoTpConfig,
+    ) -> Self {
+        EcuState {
+            response_id,
+            request_id,
+            is_engine: response_id == addressing.engine_response_id(),
+            vehicle_data: VehicleData::default(),
+            trouble_codes: TroubleCodes::default(),
+            freeze_frame: FreezeFrame::default(),
+            readiness: Readiness::default(),
+            negative_responses: NegativeResponseLog::default(),
+            reassembler: Reassembler::new(isotp),
+        }
+    }
+
+    /// The topic this ECU publishes under.
+    ///
+    /// The engine ECU keeps the plain base topic; every other ECU gets its own subtree
+    /// named after its response ID, e.g. `<base>/ecu/7E9` or `<base>/ecu/18DAF118`.
+    pub fn base_topic(&self, base_topic: &str) -> String {
+        if self.is_engine {
+            base_topic.to_string()
+        } else {
+            format!("{}/ecu/{}", base_topic, id_to_hex(self.response_id))
+        }
+    }
+}
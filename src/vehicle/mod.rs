@@ -1 +1,3 @@
 pub mod data;
+pub mod ecu;
+pub mod signals;
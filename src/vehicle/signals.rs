@@ -0,0 +1,174 @@
+// Signal registry: every value the application decodes, with its name, MQTT topic, unit,
+// scaling formula and display group.
+
+use crate::obd::formula::Expression;
+use crate::obd::request::SignalRequest;
+use crate::obd::response::DecodeError;
+
+/// Scales the data bytes that follow the PID into a value, or `None` if there are too few.
+///
+/// Bytes are widened to `f64` before any arithmetic, so scaling cannot overflow.
+pub type Decoder = fn(&[u8]) -> Option<f64>;
+
+/// How a signal's value is computed from the response data.
+#[derive(Debug, Clone)]
+pub enum Formula {
+    /// A built-in scaling function and the number of data bytes it reads.
+    Builtin { len: usize, decode: Decoder },
+    /// A formula loaded from a PID definitions file.
+    Expression(Expression),
+}
+
+impl Formula {
+    /// Number of data bytes after the echoed PID the formula needs.
+    pub fn data_len(&self) -> usize {
+        match self {
+            Formula::Builtin { len, .. } => *len,
+            Formula::Expression(expression) => expression.data_len(),
+        }
+    }
+
+    /// Compute the value from the data bytes that follow the echoed PID.
+    pub fn evaluate(&self, data: &[u8]) -> Option<f64> {
+        match self {
+            Formula::Builtin { decode, .. } => decode(data),
+            Formula::Expression(expression) => expression.evaluate(data),
+        }
+    }
+}
+
+/// Section of the console table a signal is shown in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisplayGroup {
+    Engine,
+    Throttle,
+    Temperature,
+    Pressure,
+    AirFuel,
+    Oxygen,
+    FuelTrim,
+    Emissions,
+    Counters,
+    Diesel,
+    Custom,
+}
+
+impl DisplayGroup {
+    /// Every group, in the order they are displayed.
+    pub const ALL: [DisplayGroup; 11] = [
+        DisplayGroup::Engine,
+        DisplayGroup::Throttle,
+        DisplayGroup::Temperature,
+        DisplayGroup::Pressure,
+        DisplayGroup::AirFuel,
+        DisplayGroup::Oxygen,
+        DisplayGroup::FuelTrim,
+        DisplayGroup::Emissions,
+        DisplayGroup::Counters,
+        DisplayGroup::Diesel,
+        DisplayGroup::Custom,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            DisplayGroup::Engine => "Engine and Performance",
+            DisplayGroup::Throttle => "Throttle and Pedal",
+            DisplayGroup::Temperature => "Temperature",
+            DisplayGroup::Pressure => "Pressure",
+            DisplayGroup::AirFuel => "Air and Fuel",
+            DisplayGroup::Oxygen => "O2 Sensors",
+            DisplayGroup::FuelTrim => "Fuel Trim",
+            DisplayGroup::Emissions => "EGR and Evaporative System",
+            DisplayGroup::Counters => "Counters and Timers",
+            DisplayGroup::Diesel => "Diesel and Turbo",
+            DisplayGroup::Custom => "Custom",
+        }
+    }
+}
+
+/// Describes one decoded value.
+#[derive(Debug, Clone)]
+pub struct SignalDefinition {
+    /// Unique key, also used in JSON reports, e.g. `engine_rpm`.
+    pub key: String,
+    /// Label shown in the console table.
+    pub name: String,
+    /// Suffix appended to the base topic, e.g. `RPM`.
+    pub topic: String,
+    pub unit: String,
+    /// The request whose response carries the value.
+    pub request: SignalRequest,
+    pub formula: Formula,
+    /// Decimal places used when publishing and displaying the value.
+    pub precision: usize,
+    pub group: DisplayGroup,
+}
+
+impl SignalDefinition {
+    /// Decode the signal from a positive response payload such as `41 0C 1A F8`.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` if the payload does not answer the signal's request, otherwise the
+    /// value or an error if the payload is too short or the formula has no finite result.
+    pub fn decode(&self, response: &[u8]) -> Option<Result<f64, DecodeError>> {
+        let data = self.request.response_data(response)?;
+        let expected = self.formula.data_len();
+        if data.len() < expected {
+            return Some(Err(DecodeError::Truncated {
+                signal: self.key.clone(),
+                expected,
+                got: data.len(),
+            }));
+        }
+
+        Some(
+            self.formula
+                .evaluate(data)
+                .filter(|value| value.is_finite())
+                .ok_or_else(|| DecodeError::InvalidValue(self.key.clone())),
+        )
+    }
+
+    /// Format `value` with the signal's precision, e.g. `2450.25`.
+    pub fn format(&self, value: f64) -> String {
+        format!("{:.*}", self.precision, value)
+    }
+}
+
+/// All signals known to the application.
+pub struct SignalRegistry {
+    signals: Vec<SignalDefinition>,
+}
+
+impl SignalRegistry {
+    pub fn new(signals: Vec<SignalDefinition>) -> Self {
+        SignalRegistry { signals }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &SignalDefinition> {
+        self.signals.iter()
+    }
+
+    /// Add signals, e.g. those loaded from a PID definitions file.
+    pub fn extend(&mut self, signals: impl IntoIterator<Item = SignalDefinition>) {
+        self.signals.extend(signals);
+    }
+
+    /// Data bytes a response to `request` must carry for all of its signals, or `None` if
+    /// no signal is decoded from it.
+    pub fn data_len(&self, request: &SignalRequest) -> Option<usize> {
+        self.signals
+            .iter()
+            .filter(|signal| signal.request == *request)
+            .map(|signal| signal.formula.data_len())
+            .max()
+    }
+
+    /// The signals shown in `group`, in registry order.
+    pub fn in_group(&self, group: DisplayGroup) -> impl Iterator<Item = &SignalDefinition> {
+        self.signals
+            .iter()
+            .filter(move |signal| signal.group == group)
+    }
+}